@@ -1,6 +1,12 @@
+pub mod repository;
+
+use futures::{Stream, TryStreamExt};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+
+use crate::event::envelope::EventEnvelope;
 use crate::event::EventType;
+use crate::Error;
 
 /// An aggregate is a cluster of associated events that is treated as a unit for the purpose of data changes.
 ///
@@ -14,10 +20,11 @@ use crate::event::EventType;
 /// # use event_sourcing::event::EventType;
 /// # use crate::event_sourcing::aggregate::Aggregate;
 ///
-/// # #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 /// # struct TestEvent {
 /// #     id: Uuid,
 /// #     amount: i64,
+/// #     description: String,
 /// # }
 ///
 /// # impl EventType for TestEvent {
@@ -37,6 +44,10 @@ use crate::event::EventType;
 ///     type Event = TestEvent;
 ///     type Error = Error;
 ///
+///     fn aggregate_type() -> String {
+///         String::from("TestAggregate")
+///     }
+///
 ///     fn aggregate_id(&self) -> &Self::AggregateID {
 ///         return &self.id;
 ///     }
@@ -68,6 +79,7 @@ use crate::event::EventType;
 /// let test_event = TestEvent {
 ///     id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
 ///     amount: 1,
+///     description: String::from("Deposit"),
 /// };
 /// let test_aggregate = TestAggregate::apply_all(vec![test_event]).expect("expected aggregate");
 ///
@@ -76,10 +88,303 @@ use crate::event::EventType;
 /// ```
 pub trait Aggregate: Sized + Send + Sync + Clone + Serialize + DeserializeOwned {
     type AggregateID: Send + Sync + Clone;
-    type Event: EventType;
+    type Event: EventType + Serialize + DeserializeOwned;
     type Error: Send + Sync;
 
+    fn aggregate_type() -> String;
     fn aggregate_id(&self) -> &Self::AggregateID;
     fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error>;
     fn apply_all(events: Vec<Self::Event>) -> Result<Self, Self::Error>;
+    // Schema version of the serialized aggregate, recorded in its snapshots.  Bump it whenever the
+    // shape of the aggregate changes so that older snapshots are migrated or ignored.
+    fn snapshot_schema_version() -> i64 {
+        1
+    }
+}
+
+/// A decider holds the business rules of an aggregate by turning a command into the events that
+/// should be applied to it, which keeps invariants next to the state and testable without I/O.
+///
+/// # Examples
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use uuid::Uuid;
+/// # use event_sourcing::Error;
+/// # use serde::{Deserialize, Serialize};
+/// # use event_sourcing::event::EventType;
+/// # use event_sourcing::aggregate::{Aggregate, Decider};
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     id: Uuid,
+/// #     amount: i64,
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// # }
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize)]
+/// # struct TestAggregate {
+/// #     id: Uuid,
+/// #     total: i64,
+/// # }
+///
+/// # impl Aggregate for TestAggregate {
+/// #     type AggregateID = Uuid;
+/// #     type Event = TestEvent;
+/// #     type Error = Error;
+/// #
+/// #     fn aggregate_type() -> String {
+/// #         String::from("TestAggregate")
+/// #     }
+/// #
+/// #     fn aggregate_id(&self) -> &Self::AggregateID {
+/// #         &self.id
+/// #     }
+/// #
+/// #     fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error> {
+/// #         match state {
+/// #             None => Ok(Self { id: event.id, total: event.amount }),
+/// #             Some(mut state) => {
+/// #                 state.total += event.amount;
+/// #                 Ok(state)
+/// #             }
+/// #         }
+/// #     }
+/// #
+/// #     fn apply_all(events: Vec<Self::Event>) -> Result<Self, Self::Error> {
+/// #         events
+/// #             .into_iter()
+/// #             .try_fold(None, |state, event| Self::apply(state, event).map(Some))?
+/// #             .ok_or_else(|| Error::from("Aggregate must not be None"))
+/// #     }
+/// # }
+///
+/// struct Withdraw {
+///     id: Uuid,
+///     amount: i64,
+/// }
+///
+/// impl Decider for TestAggregate {
+///     type Command = Withdraw;
+///
+///     fn decide(state: Option<&Self>, command: Self::Command) -> Result<Vec<Self::Event>, Self::Error> {
+///         match state {
+///             Some(state) if state.total >= command.amount => Ok(vec![TestEvent {
+///                 id: command.id,
+///                 amount: -command.amount,
+///                 description: String::from("Withdrawal"),
+///             }]),
+///             _ => Err(Error::from("Insufficient funds")),
+///         }
+///     }
+/// }
+///
+/// let id = Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid");
+/// let deposit = TestEvent { id, amount: 5, description: String::from("Deposit") };
+/// let test_aggregate = TestAggregate::apply_all(vec![deposit]).expect("expected aggregate");
+/// let events = TestAggregate::decide(Some(&test_aggregate), Withdraw { id, amount: 3 }).expect("expected events");
+///
+/// # assert_eq!(events, vec![TestEvent { id, amount: -3, description: String::from("Withdrawal") }]);
+/// # assert!(TestAggregate::decide(Some(&test_aggregate), Withdraw { id, amount: 6 }).is_err());
+/// # assert!(TestAggregate::decide(None, Withdraw { id, amount: 1 }).is_err());
+/// ```
+pub trait Decider: Aggregate {
+    type Command: Send + Sync;
+
+    // Decide which events the command produces given the current state, which is `None` when the
+    // aggregate does not exist yet.
+    fn decide(
+        state: Option<&Self>,
+        command: Self::Command,
+    ) -> Result<Vec<Self::Event>, Self::Error>;
+}
+
+/// Apply a stream of event envelopes to the state of an aggregate one envelope at a time, without
+/// collecting the stream, and return the resulting state along with the version of the last
+/// envelope.  `version` is returned as is when the stream is empty.
+///
+/// # Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use uuid::Uuid;
+/// # use event_sourcing::Error;
+/// # use serde::{Deserialize, Serialize};
+/// # use event_sourcing::aggregate::{fold, Aggregate};
+/// # use event_sourcing::event::envelope::EventEnvelope;
+/// # use event_sourcing::event::store::{EventStore, ExpectedVersion};
+/// # use event_sourcing::event::store::in_memory::InMemoryEventStore;
+/// # use event_sourcing::event::EventType;
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     id: Uuid,
+/// #     amount: i64,
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// # }
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize)]
+/// # struct TestAggregate {
+/// #     id: Uuid,
+/// #     total: i64,
+/// # }
+///
+/// # impl Aggregate for TestAggregate {
+/// #     type AggregateID = Uuid;
+/// #     type Event = TestEvent;
+/// #     type Error = Error;
+/// #
+/// #     fn aggregate_type() -> String {
+/// #         String::from("TestAggregate")
+/// #     }
+/// #
+/// #     fn aggregate_id(&self) -> &Self::AggregateID {
+/// #         &self.id
+/// #     }
+/// #
+/// #     fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error> {
+/// #         match state {
+/// #             None => Ok(Self { id: event.id, total: event.amount }),
+/// #             Some(mut state) => {
+/// #                 state.total += event.amount;
+/// #                 Ok(state)
+/// #             }
+/// #         }
+/// #     }
+/// #
+/// #     fn apply_all(events: Vec<Self::Event>) -> Result<Self, Self::Error> {
+/// #         events
+/// #             .into_iter()
+/// #             .try_fold(None, |state, event| Self::apply(state, event).map(Some))?
+/// #             .ok_or_else(|| Error::from("Aggregate must not be None"))
+/// #     }
+/// # }
+///
+/// # futures::executor::block_on(async {
+/// # let id = Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid");
+/// # let event_store = InMemoryEventStore::default();
+/// # let event_envelopes = (0..3)
+/// #     .map(|_| {
+/// #         EventEnvelope::new(
+/// #             id.to_string(),
+/// #             TestAggregate::aggregate_type(),
+/// #             TestEvent { id, amount: 1, description: String::from("Deposit") },
+/// #             String::from("TestEvent"),
+/// #             0,
+/// #         )
+/// #     })
+/// #     .collect();
+/// # event_store
+/// #     .append(&id.to_string(), event_envelopes, ExpectedVersion::NoStream)
+/// #     .await
+/// #     .expect("expected appended events");
+/// let (state, version) = fold::<TestAggregate, _>(None, 0, event_store.stream(&id.to_string()))
+///     .await
+///     .expect("expected aggregate");
+///
+/// # assert_eq!(version, 3);
+/// # assert_eq!(state.expect("expected aggregate").total, 3);
+/// # });
+/// ```
+pub async fn fold<A, S>(
+    state: Option<A>,
+    version: i64,
+    event_envelopes: S,
+) -> Result<(Option<A>, i64), Error>
+where
+    A: Aggregate,
+    S: Stream<Item = Result<EventEnvelope<A::Event>, Error>>,
+    Error: From<A::Error>,
+{
+    event_envelopes
+        .try_fold((state, version), |(state, _), event_envelope| async move {
+            Ok((
+                Some(A::apply(state, event_envelope.data)?),
+                event_envelope.version,
+            ))
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_event, test_id, TestAggregate, TestCommand};
+
+    #[test]
+    fn it_rejects_command_breaking_invariant() {
+        let test_aggregate =
+            TestAggregate::apply_all(vec![test_event(5)]).expect("expected aggregate");
+
+        let result = TestAggregate::decide(
+            Some(&test_aggregate),
+            TestCommand::Withdraw {
+                id: test_id(),
+                amount: 6,
+            },
+        );
+
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+        assert!(matches!(
+            TestAggregate::decide(
+                None,
+                TestCommand::Withdraw {
+                    id: test_id(),
+                    amount: 1,
+                },
+            ),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn it_decides_then_applies_on_empty_state() {
+        let events = TestAggregate::decide(
+            None,
+            TestCommand::Deposit {
+                id: test_id(),
+                amount: 5,
+            },
+        )
+        .expect("expected events");
+        let test_aggregate = events
+            .into_iter()
+            .try_fold(None, |state, event| {
+                TestAggregate::apply(state, event).map(Some)
+            })
+            .expect("expected aggregate")
+            .expect("expected aggregate");
+
+        assert_eq!(test_aggregate.id, test_id());
+        assert_eq!(test_aggregate.total, 5);
+
+        let events = TestAggregate::decide(
+            Some(&test_aggregate),
+            TestCommand::Withdraw {
+                id: test_id(),
+                amount: 3,
+            },
+        )
+        .expect("expected events");
+        let test_aggregate = events
+            .into_iter()
+            .try_fold(test_aggregate, |state, event| {
+                TestAggregate::apply(Some(state), event)
+            })
+            .expect("expected aggregate");
+
+        assert_eq!(test_aggregate.total, 2);
+    }
 }
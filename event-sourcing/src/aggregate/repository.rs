@@ -0,0 +1,961 @@
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use futures::{future, TryStreamExt};
+
+use crate::aggregate::{fold, Aggregate};
+use crate::event::envelope::EventEnvelope;
+use crate::event::metadata::EventMetadata;
+use crate::event::store::{EventStore, ExpectedVersion};
+use crate::event::EventType;
+use crate::snapshot::envelope::SnapshotEnvelope;
+use crate::snapshot::policy::{
+    SnapshotContext, SnapshotErrorHandler, SnapshotMode, SnapshotPolicy,
+};
+use crate::snapshot::store::{NoSnapshotStore, SnapshotStore};
+use crate::Error;
+
+/// Aggregate rebuilt by the repository, along with what it took to rebuild it.
+#[derive(Debug, Clone)]
+pub struct LoadedAggregate<A> {
+    // Current state of the aggregate, `None` when it does not have any events yet.
+    pub state: Option<A>,
+    // Version of the aggregate after its last event.
+    pub version: i64,
+    // Version of the snapshot the aggregate was rebuilt from, or `0` when it was rebuilt without one.
+    pub snapshot_version: i64,
+    // Time it took to read the snapshot and replay the events on top of it.
+    pub replay_duration: Duration,
+}
+
+/// Repository that loads aggregates from, and saves their new events to, an event store.
+///
+/// When a snapshot store is provided the aggregate is rebuilt from its latest snapshot and only the
+/// events after it are replayed.  Snapshot policies decide when `commit` takes a new snapshot of the
+/// aggregate, so long-lived aggregates do not replay their whole history on every command.
+///
+/// # Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use uuid::Uuid;
+/// # use event_sourcing::Error;
+/// # use serde::{Deserialize, Serialize};
+/// # use event_sourcing::aggregate::Aggregate;
+/// # use event_sourcing::aggregate::repository::AggregateRepository;
+/// # use event_sourcing::event::EventType;
+/// # use event_sourcing::event::store::ExpectedVersion;
+/// # use event_sourcing::event::store::in_memory::InMemoryEventStore;
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     id: Uuid,
+/// #     amount: i64,
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// # }
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize)]
+/// # struct TestAggregate {
+/// #     id: Uuid,
+/// #     total: i64,
+/// # }
+///
+/// # impl Aggregate for TestAggregate {
+/// #     type AggregateID = Uuid;
+/// #     type Event = TestEvent;
+/// #     type Error = Error;
+/// #
+/// #     fn aggregate_type() -> String {
+/// #         String::from("TestAggregate")
+/// #     }
+/// #
+/// #     fn aggregate_id(&self) -> &Self::AggregateID {
+/// #         &self.id
+/// #     }
+/// #
+/// #     fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error> {
+/// #         match state {
+/// #             None => Ok(Self { id: event.id, total: event.amount }),
+/// #             Some(mut state) => {
+/// #                 state.total += event.amount;
+/// #                 Ok(state)
+/// #             }
+/// #         }
+/// #     }
+/// #
+/// #     fn apply_all(events: Vec<Self::Event>) -> Result<Self, Self::Error> {
+/// #         events
+/// #             .into_iter()
+/// #             .try_fold(None, |state, event| Self::apply(state, event).map(Some))?
+/// #             .ok_or_else(|| Error::from("Aggregate must not be None"))
+/// #     }
+/// # }
+///
+/// # futures::executor::block_on(async {
+/// # let id = Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid");
+/// let repository: AggregateRepository<TestAggregate, _> =
+///     AggregateRepository::new(InMemoryEventStore::default());
+/// let (_, version) = repository.load(&id).await.expect("expected aggregate");
+/// let version = repository
+///     .save(
+///         &id,
+///         vec![TestEvent { id, amount: 1, description: String::from("Deposit") }],
+///         ExpectedVersion::Exact(version),
+///     )
+///     .await
+///     .expect("expected saved events");
+/// let (state, version) = repository.load(&id).await.expect("expected aggregate");
+///
+/// # assert_eq!(version, 1);
+/// # assert_eq!(state.expect("expected aggregate").total, 1);
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct AggregateRepository<A, E, S = NoSnapshotStore>
+where
+    A: Aggregate,
+    E: EventStore,
+    S: SnapshotStore,
+{
+    event_store: E,
+    snapshot_store: S,
+    snapshot_policies: Vec<SnapshotPolicy>,
+    snapshot_mode: SnapshotMode,
+    snapshot_error_handler: Option<SnapshotErrorHandler>,
+    aggregate: PhantomData<A>,
+}
+
+impl<A, E> AggregateRepository<A, E>
+where
+    A: Aggregate,
+    E: EventStore,
+{
+    /// Create a repository that always rebuilds aggregates from all of their events.
+    pub fn new(event_store: E) -> Self {
+        Self {
+            event_store,
+            snapshot_store: NoSnapshotStore,
+            snapshot_policies: Vec::new(),
+            snapshot_mode: SnapshotMode::default(),
+            snapshot_error_handler: None,
+            aggregate: PhantomData,
+        }
+    }
+}
+
+impl<A, E, S> AggregateRepository<A, E, S>
+where
+    A: Aggregate,
+    A::AggregateID: ToString,
+    E: EventStore,
+    S: SnapshotStore,
+    Error: From<A::Error>,
+{
+    /// Create a repository that rebuilds aggregates from their latest snapshot.
+    pub fn with_snapshot_store(event_store: E, snapshot_store: S) -> Self {
+        Self {
+            event_store,
+            snapshot_store,
+            snapshot_policies: Vec::new(),
+            snapshot_mode: SnapshotMode::default(),
+            snapshot_error_handler: None,
+            aggregate: PhantomData,
+        }
+    }
+
+    /// Take a snapshot in `commit` whenever the policy fires.  A snapshot is taken when any of the
+    /// configured policies fires.
+    pub fn with_snapshot_policy(mut self, snapshot_policy: SnapshotPolicy) -> Self {
+        self.snapshot_policies.push(snapshot_policy);
+        self
+    }
+
+    /// Set whether snapshots are persisted inline or in the background.
+    pub fn with_snapshot_mode(mut self, snapshot_mode: SnapshotMode) -> Self {
+        self.snapshot_mode = snapshot_mode;
+        self
+    }
+
+    /// Report snapshots that could not be taken to the handler.  They never fail `commit`, since
+    /// the events have been saved by then, and are otherwise dropped.
+    pub fn with_snapshot_error_handler(
+        mut self,
+        snapshot_error_handler: SnapshotErrorHandler,
+    ) -> Self {
+        self.snapshot_error_handler = Some(snapshot_error_handler);
+        self
+    }
+
+    /// Load the current state of the aggregate along with its version.  The state is `None` and the
+    /// version is `0` when the aggregate does not have any events yet or its stream has been deleted.
+    pub async fn load(&self, aggregate_id: &A::AggregateID) -> Result<(Option<A>, i64), Error> {
+        let aggregate = self.load_aggregate(aggregate_id).await?;
+        Ok((aggregate.state, aggregate.version))
+    }
+
+    /// Load the current state of the aggregate along with its version and how it was rebuilt, to be
+    /// passed back to `commit`.
+    pub async fn load_aggregate(
+        &self,
+        aggregate_id: &A::AggregateID,
+    ) -> Result<LoadedAggregate<A>, Error> {
+        let started = Instant::now();
+        let aggregate_id = aggregate_id.to_string();
+        // Events that are skipped when read still count towards the version the aggregate is
+        // committed at.  Deleted streams are at version `0`, so their snapshots are not used either.
+        let stored_version = self.event_store.read_version(&aggregate_id).await?;
+        let snapshot_envelope = match stored_version {
+            0 => None,
+            _ => self.snapshot_store.read::<A>(&aggregate_id).await?,
+        };
+        let snapshot_version = snapshot_envelope
+            .as_ref()
+            .map_or(0, |snapshot_envelope| snapshot_envelope.version);
+        let (state, version) = self
+            .replay_until(&aggregate_id, snapshot_envelope, |_| true)
+            .await?;
+        Ok(LoadedAggregate {
+            state,
+            version: version.max(stored_version),
+            snapshot_version,
+            replay_duration: started.elapsed(),
+        })
+    }
+
+    /// Load the state of the aggregate as it was at the given version, starting from the closest
+    /// snapshot taken at or before it.  The returned version is lower than the requested one when
+    /// the aggregate does not have that many events.
+    pub async fn load_as_of_version(
+        &self,
+        aggregate_id: &A::AggregateID,
+        version: i64,
+    ) -> Result<(Option<A>, i64), Error> {
+        let aggregate_id = aggregate_id.to_string();
+        let snapshot_envelope = match self.event_store.read_version(&aggregate_id).await? {
+            0 => None,
+            _ => {
+                self.snapshot_store
+                    .read_at_version::<A>(&aggregate_id, version)
+                    .await?
+            }
+        };
+        self.replay_until(&aggregate_id, snapshot_envelope, |event_envelope| {
+            event_envelope.version <= version
+        })
+        .await
+    }
+
+    /// Load the state of the aggregate as it was at the given point in time, only applying events
+    /// recorded at or before it and starting from the closest snapshot taken at or before it.
+    pub async fn load_as_of(
+        &self,
+        aggregate_id: &A::AggregateID,
+        timestamp: DateTime<Utc>,
+    ) -> Result<(Option<A>, i64), Error> {
+        let aggregate_id = aggregate_id.to_string();
+        let snapshot_envelope = match self.event_store.read_version(&aggregate_id).await? {
+            0 => None,
+            _ => {
+                self.snapshot_store
+                    .read_at_timestamp::<A>(&aggregate_id, timestamp)
+                    .await?
+            }
+        };
+        self.replay_until(&aggregate_id, snapshot_envelope, |event_envelope| {
+            event_envelope.timestamp <= timestamp
+        })
+        .await
+    }
+
+    // Apply the events following the snapshot for as long as they match the predicate.
+    async fn replay_until(
+        &self,
+        aggregate_id: &String,
+        snapshot_envelope: Option<SnapshotEnvelope<A>>,
+        predicate: impl Fn(&EventEnvelope<A::Event>) -> bool,
+    ) -> Result<(Option<A>, i64), Error> {
+        let (state, snapshot_version) = match snapshot_envelope {
+            Some(snapshot_envelope) => (Some(snapshot_envelope.data), snapshot_envelope.version),
+            None => (None, 0),
+        };
+        fold(
+            state,
+            snapshot_version,
+            self.event_store
+                .stream_from::<A::Event>(aggregate_id, snapshot_version + 1)
+                .try_take_while(|event_envelope| future::ready(Ok(predicate(event_envelope)))),
+        )
+        .await
+    }
+
+    /// Persist new events on top of a loaded aggregate, expecting it to still be at the loaded
+    /// version, and return its version after the last event.  When one of the snapshot policies
+    /// fires, a snapshot of the resulting state is persisted according to the snapshot mode.  A
+    /// snapshot that cannot be taken is reported to the snapshot error handler rather than failing
+    /// the commit, since the events have already been saved.
+    pub async fn commit(
+        &self,
+        aggregate_id: &A::AggregateID,
+        aggregate: LoadedAggregate<A>,
+        events: Vec<A::Event>,
+        metadata: EventMetadata,
+    ) -> Result<i64, Error>
+    where
+        A: 'static,
+        S: 'static,
+    {
+        let version = self
+            .save_with_metadata(
+                aggregate_id,
+                events.clone(),
+                ExpectedVersion::Exact(aggregate.version),
+                metadata,
+            )
+            .await?;
+        let context = SnapshotContext {
+            version,
+            snapshot_version: aggregate.snapshot_version,
+            replayed_events: aggregate.version - aggregate.snapshot_version,
+            replay_duration: aggregate.replay_duration,
+        };
+        if !self
+            .snapshot_policies
+            .iter()
+            .any(|snapshot_policy| snapshot_policy.should_snapshot(&context))
+        {
+            return Ok(version);
+        }
+        let aggregate_id = aggregate_id.to_string();
+        let state = match events
+            .into_iter()
+            .try_fold(aggregate.state, |state, event| {
+                A::apply(state, event).map(Some)
+            }) {
+            Ok(Some(state)) => state,
+            Ok(None) => return Ok(version),
+            Err(error) => {
+                self.report_snapshot_error(&aggregate_id, &Error::from(error));
+                return Ok(version);
+            }
+        };
+        let snapshot_envelope =
+            SnapshotEnvelope::new(aggregate_id.clone(), A::aggregate_type(), state, version);
+        match self.snapshot_mode {
+            SnapshotMode::Inline => {
+                if let Err(error) = self.snapshot_store.persist(snapshot_envelope).await {
+                    self.report_snapshot_error(&aggregate_id, &error);
+                }
+            }
+            SnapshotMode::Background(spawn) => {
+                let snapshot_store = self.snapshot_store.clone();
+                let snapshot_error_handler = self.snapshot_error_handler;
+                spawn(Box::pin(async move {
+                    if let Err(error) = snapshot_store.persist(snapshot_envelope).await {
+                        if let Some(snapshot_error_handler) = snapshot_error_handler {
+                            snapshot_error_handler(&aggregate_id, &error);
+                        }
+                    }
+                }))
+            }
+        }
+        Ok(version)
+    }
+
+    // Hand an error taking a snapshot of the aggregate to the snapshot error handler, if any.
+    fn report_snapshot_error(&self, aggregate_id: &str, error: &Error) {
+        if let Some(snapshot_error_handler) = self.snapshot_error_handler {
+            snapshot_error_handler(aggregate_id, error);
+        }
+    }
+
+    /// Tombstone the stream of the aggregate and erase its events and snapshots, e.g. to honour a
+    /// request for erasure.
+    pub async fn hard_delete(
+        &self,
+        aggregate_id: &A::AggregateID,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        let aggregate_id = aggregate_id.to_string();
+        self.event_store
+            .hard_delete(&aggregate_id, expected_version)
+            .await?;
+        self.snapshot_store.delete(&aggregate_id).await
+    }
+
+    /// Persist new events for the aggregate and return its version after the last event.
+    pub async fn save(
+        &self,
+        aggregate_id: &A::AggregateID,
+        events: Vec<A::Event>,
+        expected_version: ExpectedVersion,
+    ) -> Result<i64, Error> {
+        self.save_with_metadata(
+            aggregate_id,
+            events,
+            expected_version,
+            EventMetadata::default(),
+        )
+        .await
+    }
+
+    /// Persist new events for the aggregate, each carrying the given metadata, and return its
+    /// version after the last event.
+    pub async fn save_with_metadata(
+        &self,
+        aggregate_id: &A::AggregateID,
+        events: Vec<A::Event>,
+        expected_version: ExpectedVersion,
+        metadata: EventMetadata,
+    ) -> Result<i64, Error> {
+        let aggregate_id = aggregate_id.to_string();
+        // Events saved at any version are left at version `0` for the event store to assign.
+        let first_version = match expected_version {
+            ExpectedVersion::Exact(version) => Some(version + 1),
+            ExpectedVersion::NoStream => Some(1),
+            ExpectedVersion::Any => None,
+        };
+        let event_envelopes = events
+            .into_iter()
+            .enumerate()
+            .map(|(index, event)| {
+                let version = first_version.map_or(0, |version| version + index as i64);
+                let event_type = event.event_type();
+                EventEnvelope::new(
+                    aggregate_id.clone(),
+                    A::aggregate_type(),
+                    event,
+                    event_type,
+                    version,
+                )
+                .with_metadata(metadata.clone())
+            })
+            .collect();
+        self.event_store
+            .append(&aggregate_id, event_envelopes, expected_version)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::OnceLock;
+
+    use serde::{Deserialize, Serialize};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::event::metadata::StreamMetadata;
+    use crate::event::registry::EventRegistry;
+    use crate::event::store::in_memory::InMemoryEventStore;
+    use crate::fixtures::{test_event, test_id, TestAggregate, TestEvent};
+    use crate::snapshot::envelope::SnapshotEnvelope;
+    use crate::snapshot::store::in_memory::InMemorySnapshotStore;
+
+    // Deposit decoded through a registry that skips the event types it does not know.
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    struct RegisteredEvent {
+        id: Uuid,
+        amount: i64,
+    }
+
+    impl EventType for RegisteredEvent {
+        fn event_type(&self) -> String {
+            String::from("RegisteredEvent")
+        }
+
+        fn registry() -> Option<&'static EventRegistry<Self>> {
+            static REGISTRY: OnceLock<EventRegistry<RegisteredEvent>> = OnceLock::new();
+            Some(REGISTRY.get_or_init(|| {
+                EventRegistry::default()
+                    .register("RegisteredEvent", std::convert::identity)
+                    .skip_unknown()
+            }))
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct RegisteredAggregate {
+        id: Uuid,
+        total: i64,
+    }
+
+    impl Aggregate for RegisteredAggregate {
+        type AggregateID = Uuid;
+        type Event = RegisteredEvent;
+        type Error = Error;
+
+        fn aggregate_type() -> String {
+            String::from("RegisteredAggregate")
+        }
+
+        fn aggregate_id(&self) -> &Self::AggregateID {
+            &self.id
+        }
+
+        fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error> {
+            let total = state.map_or(0, |state| state.total);
+            Ok(Self {
+                id: event.id,
+                total: total + event.amount,
+            })
+        }
+
+        fn apply_all(events: Vec<Self::Event>) -> Result<Self, Self::Error> {
+            events
+                .into_iter()
+                .try_fold(None, |state, event| Self::apply(state, event).map(Some))?
+                .ok_or_else(|| Error::from("Aggregate must not be None"))
+        }
+    }
+
+    fn registered_event(amount: i64) -> RegisteredEvent {
+        RegisteredEvent {
+            id: test_id(),
+            amount,
+        }
+    }
+
+    #[tokio::test]
+    async fn it_loads_nothing_for_new_aggregate() {
+        let repository: AggregateRepository<TestAggregate, _> =
+            AggregateRepository::new(InMemoryEventStore::default());
+        let (state, version) = repository
+            .load(&test_id())
+            .await
+            .expect("expected aggregate");
+        assert!(state.is_none());
+        assert_eq!(version, 0);
+    }
+
+    #[tokio::test]
+    async fn it_saves_and_loads_aggregate() {
+        let event_store = InMemoryEventStore::default();
+        let repository: AggregateRepository<TestAggregate, _> =
+            AggregateRepository::new(event_store.clone());
+        let id = test_id();
+        let version = repository
+            .save(
+                &id,
+                vec![test_event(1), test_event(2)],
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected saved events");
+        assert_eq!(version, 2);
+        let version = repository
+            .save(&id, vec![test_event(3)], ExpectedVersion::Exact(version))
+            .await
+            .expect("expected saved events");
+        assert_eq!(version, 3);
+
+        let (state, version) = repository.load(&id).await.expect("expected aggregate");
+        assert_eq!(version, 3);
+        assert_eq!(state.expect("expected aggregate").total, 6);
+
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&id.to_string())
+            .await
+            .expect("expected events");
+        assert!(event_envelopes.iter().all(|event_envelope| {
+            event_envelope.aggregate_type == "TestAggregate"
+                && event_envelope.event_type == "TestEvent"
+        }));
+        assert_eq!(
+            event_envelopes
+                .iter()
+                .map(|event_envelope| event_envelope.version)
+                .collect::<Vec<i64>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_counts_skipped_events_towards_version() {
+        let event_store = InMemoryEventStore::default();
+        let repository: AggregateRepository<RegisteredAggregate, _> =
+            AggregateRepository::new(event_store.clone());
+        let id = test_id();
+        repository
+            .save(&id, vec![registered_event(1)], ExpectedVersion::NoStream)
+            .await
+            .expect("expected saved events");
+        let closed = EventEnvelope::new(
+            id.to_string(),
+            RegisteredAggregate::aggregate_type(),
+            registered_event(0),
+            String::from("Closed"),
+            2,
+        );
+        event_store
+            .persist(closed, ExpectedVersion::Exact(1))
+            .await
+            .expect("expected persisted event");
+
+        let (state, version) = repository.load(&id).await.expect("expected aggregate");
+        assert_eq!(version, 2);
+        assert_eq!(state.expect("expected aggregate").total, 1);
+        let version = repository
+            .save(
+                &id,
+                vec![registered_event(2)],
+                ExpectedVersion::Exact(version),
+            )
+            .await
+            .expect("expected saved events");
+        assert_eq!(version, 3);
+    }
+
+    #[tokio::test]
+    async fn it_saves_after_stream_retention_expired() {
+        let event_store = InMemoryEventStore::default();
+        let repository: AggregateRepository<TestAggregate, _> =
+            AggregateRepository::new(event_store.clone());
+        let id = test_id();
+        for version in 1..=2 {
+            let mut event_envelope = EventEnvelope::new(
+                id.to_string(),
+                TestAggregate::aggregate_type(),
+                test_event(version),
+                String::from("TestEvent"),
+                version,
+            );
+            event_envelope.timestamp = Utc::now() - chrono::Duration::hours(2);
+            event_store
+                .persist(event_envelope, ExpectedVersion::Exact(version - 1))
+                .await
+                .expect("expected persisted event");
+        }
+        event_store
+            .write_stream_metadata(
+                &id.to_string(),
+                StreamMetadata::default().with_max_age(Duration::from_secs(3600)),
+            )
+            .await
+            .expect("expected written metadata");
+
+        let (state, version) = repository.load(&id).await.expect("expected aggregate");
+        assert_eq!(version, 2);
+        assert_eq!(state.expect("expected aggregate").total, 2);
+        let version = repository
+            .save(&id, vec![test_event(3)], ExpectedVersion::Exact(version))
+            .await
+            .expect("expected saved events");
+        assert_eq!(version, 3);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_save_at_stale_version() {
+        let repository: AggregateRepository<TestAggregate, _> =
+            AggregateRepository::new(InMemoryEventStore::default());
+        let id = test_id();
+        repository
+            .save(&id, vec![test_event(1)], ExpectedVersion::NoStream)
+            .await
+            .expect("expected saved events");
+        let error = repository
+            .save(&id, vec![test_event(2)], ExpectedVersion::Exact(0))
+            .await
+            .expect_err("expected version conflict");
+        assert!(matches!(error, Error::VersionConflict(_)));
+    }
+
+    #[tokio::test]
+    async fn it_loads_aggregate_from_latest_snapshot() {
+        let snapshot_store = InMemorySnapshotStore::default();
+        let repository: AggregateRepository<TestAggregate, _, _> =
+            AggregateRepository::with_snapshot_store(
+                InMemoryEventStore::default(),
+                snapshot_store.clone(),
+            );
+        let id = test_id();
+        let version = repository
+            .save(
+                &id,
+                vec![test_event(1), test_event(2), test_event(3)],
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected saved events");
+        // The snapshot total differs from the replayed total so the test can tell them apart.
+        snapshot_store
+            .persist(SnapshotEnvelope::new(
+                id.to_string(),
+                TestAggregate::aggregate_type(),
+                TestAggregate { id, total: 100 },
+                2,
+            ))
+            .await
+            .expect("expected persisted snapshot");
+
+        let (state, loaded_version) = repository.load(&id).await.expect("expected aggregate");
+        assert_eq!(loaded_version, version);
+        assert_eq!(state.expect("expected aggregate").total, 103);
+    }
+
+    // Save three events for the aggregate and take a snapshot at version 2.
+    async fn save_with_snapshot(
+        repository: &AggregateRepository<TestAggregate, InMemoryEventStore, InMemorySnapshotStore>,
+        snapshot_store: &InMemorySnapshotStore,
+    ) -> Uuid {
+        let id = test_id();
+        repository
+            .save(
+                &id,
+                vec![test_event(1), test_event(2), test_event(3)],
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected saved events");
+        snapshot_store
+            .persist(SnapshotEnvelope::new(
+                id.to_string(),
+                TestAggregate::aggregate_type(),
+                TestAggregate { id, total: 100 },
+                2,
+            ))
+            .await
+            .expect("expected persisted snapshot");
+        id
+    }
+
+    #[tokio::test]
+    async fn it_hides_soft_deleted_aggregate_with_snapshot() {
+        let event_store = InMemoryEventStore::default();
+        let snapshot_store = InMemorySnapshotStore::default();
+        let repository =
+            AggregateRepository::with_snapshot_store(event_store.clone(), snapshot_store.clone());
+        let id = save_with_snapshot(&repository, &snapshot_store).await;
+        event_store
+            .soft_delete(&id.to_string(), ExpectedVersion::Exact(3))
+            .await
+            .expect("expected deleted stream");
+
+        let (state, version) = repository.load(&id).await.expect("expected no error");
+        assert!(state.is_none());
+        assert_eq!(version, 0);
+        let (state, _) = repository
+            .load_as_of_version(&id, 3)
+            .await
+            .expect("expected no error");
+        assert!(state.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_erases_snapshots_of_hard_deleted_aggregate() {
+        let snapshot_store = InMemorySnapshotStore::default();
+        let repository = AggregateRepository::with_snapshot_store(
+            InMemoryEventStore::default(),
+            snapshot_store.clone(),
+        );
+        let id = save_with_snapshot(&repository, &snapshot_store).await;
+        repository
+            .hard_delete(&id, ExpectedVersion::Exact(3))
+            .await
+            .expect("expected deleted aggregate");
+
+        let (state, version) = repository.load(&id).await.expect("expected no error");
+        assert!(state.is_none());
+        assert_eq!(version, 0);
+        assert!(snapshot_store
+            .read::<TestAggregate>(&id.to_string())
+            .await
+            .expect("expected no error")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn it_loads_aggregate_as_of_version_and_timestamp() {
+        let snapshot_store = InMemorySnapshotStore::default();
+        let repository: AggregateRepository<TestAggregate, _, _> =
+            AggregateRepository::with_snapshot_store(
+                InMemoryEventStore::default(),
+                snapshot_store.clone(),
+            );
+        let id = test_id();
+        repository
+            .save(
+                &id,
+                vec![test_event(1), test_event(2)],
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected saved events");
+        // The snapshot totals differ from the replayed totals so the test can tell them apart.
+        snapshot_store
+            .persist(SnapshotEnvelope::new(
+                id.to_string(),
+                TestAggregate::aggregate_type(),
+                TestAggregate { id, total: 100 },
+                2,
+            ))
+            .await
+            .expect("expected persisted snapshot");
+        let timestamp = Utc::now();
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        repository
+            .save(&id, vec![test_event(3)], ExpectedVersion::Exact(2))
+            .await
+            .expect("expected saved events");
+
+        let (state, version) = repository
+            .load_as_of_version(&id, 1)
+            .await
+            .expect("expected aggregate");
+        assert_eq!(version, 1);
+        assert_eq!(state.expect("expected aggregate").total, 1);
+        let (state, version) = repository
+            .load_as_of_version(&id, 5)
+            .await
+            .expect("expected aggregate");
+        assert_eq!(version, 3);
+        assert_eq!(state.expect("expected aggregate").total, 103);
+        let (state, version) = repository
+            .load_as_of(&id, timestamp)
+            .await
+            .expect("expected aggregate");
+        assert_eq!(version, 2);
+        assert_eq!(state.expect("expected aggregate").total, 100);
+        let (state, version) = repository
+            .load_as_of(&id, timestamp - chrono::Duration::days(1))
+            .await
+            .expect("expected aggregate");
+        assert_eq!(version, 0);
+        assert!(state.is_none());
+    }
+
+    async fn commit_deposits<S: SnapshotStore + 'static>(
+        repository: &AggregateRepository<TestAggregate, InMemoryEventStore, S>,
+        deposits: i64,
+    ) {
+        let id = test_id();
+        for _ in 0..deposits {
+            let aggregate = repository
+                .load_aggregate(&id)
+                .await
+                .expect("expected aggregate");
+            repository
+                .commit(
+                    &id,
+                    aggregate,
+                    vec![test_event(1)],
+                    EventMetadata::default(),
+                )
+                .await
+                .expect("expected committed events");
+        }
+    }
+
+    #[tokio::test]
+    async fn it_takes_snapshot_every_n_events() {
+        let snapshot_store = InMemorySnapshotStore::default();
+        let repository = AggregateRepository::with_snapshot_store(
+            InMemoryEventStore::default(),
+            snapshot_store.clone(),
+        )
+        .with_snapshot_policy(SnapshotPolicy::EveryNEvents(3));
+        commit_deposits(&repository, 7).await;
+
+        let snapshot_envelope = snapshot_store
+            .read::<TestAggregate>(&test_id().to_string())
+            .await
+            .expect("expected no error")
+            .expect("expected snapshot");
+        assert_eq!(snapshot_envelope.version, 6);
+        assert_eq!(snapshot_envelope.data.total, 6);
+        let aggregate = repository
+            .load_aggregate(&test_id())
+            .await
+            .expect("expected aggregate");
+        assert_eq!(aggregate.snapshot_version, 6);
+        assert_eq!(aggregate.version, 7);
+        assert_eq!(aggregate.state.expect("expected aggregate").total, 7);
+    }
+
+    #[tokio::test]
+    async fn it_takes_snapshot_in_background_when_predicate_fires() {
+        let snapshot_store = InMemorySnapshotStore::default();
+        let repository = AggregateRepository::with_snapshot_store(
+            InMemoryEventStore::default(),
+            snapshot_store.clone(),
+        )
+        .with_snapshot_policy(SnapshotPolicy::Custom(|context| context.version == 2))
+        .with_snapshot_mode(SnapshotMode::Background(|future| {
+            std::thread::spawn(move || futures::executor::block_on(future))
+                .join()
+                .expect("expected persisted snapshot")
+        }));
+        commit_deposits(&repository, 3).await;
+
+        let snapshot_envelope = snapshot_store
+            .read::<TestAggregate>(&test_id().to_string())
+            .await
+            .expect("expected no error")
+            .expect("expected snapshot");
+        assert_eq!(snapshot_envelope.version, 2);
+    }
+
+    // Snapshot store whose every write fails.
+    #[derive(Debug, Clone)]
+    struct FailingSnapshotStore;
+
+    #[async_trait::async_trait]
+    impl SnapshotStore for FailingSnapshotStore {
+        async fn read<A: Aggregate>(
+            &self,
+            _aggregate_id: &String,
+        ) -> Result<Option<SnapshotEnvelope<A>>, Error> {
+            Ok(None)
+        }
+
+        async fn persist<A: Aggregate>(
+            &self,
+            _snapshot_envelope: SnapshotEnvelope<A>,
+        ) -> Result<(), Error> {
+            Err(Error::backend("snapshot store unavailable"))
+        }
+
+        async fn delete(&self, _aggregate_id: &String) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn it_reports_snapshot_errors_without_failing_commit() {
+        static INLINE_ERRORS: AtomicUsize = AtomicUsize::new(0);
+        static BACKGROUND_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+        let repository = AggregateRepository::with_snapshot_store(
+            InMemoryEventStore::default(),
+            FailingSnapshotStore,
+        )
+        .with_snapshot_policy(SnapshotPolicy::EveryNEvents(1))
+        .with_snapshot_error_handler(|_, _| {
+            INLINE_ERRORS.fetch_add(1, Ordering::SeqCst);
+        });
+        commit_deposits(&repository, 2).await;
+        assert_eq!(INLINE_ERRORS.load(Ordering::SeqCst), 2);
+
+        let repository = repository
+            .with_snapshot_mode(SnapshotMode::Background(futures::executor::block_on))
+            .with_snapshot_error_handler(|aggregate_id, error| {
+                assert_eq!(aggregate_id, test_id().to_string());
+                assert!(matches!(error, Error::Backend(_)));
+                BACKGROUND_ERRORS.fetch_add(1, Ordering::SeqCst);
+            });
+        commit_deposits(&repository, 1).await;
+        assert_eq!(BACKGROUND_ERRORS.load(Ordering::SeqCst), 1);
+        let (_, version) = repository
+            .load(&test_id())
+            .await
+            .expect("expected aggregate");
+        assert_eq!(version, 3);
+    }
+}
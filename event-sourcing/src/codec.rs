@@ -0,0 +1,368 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// Content types of the built-in codecs, recorded next to encoded envelopes so that a history
+/// written with different codecs can still be decoded.
+///
+/// Every content type can be parsed and recorded, but encoding or decoding with MessagePack, CBOR
+/// or bincode requires the `msgpack`, `cbor` or `bincode` feature respectively.  A content type is
+/// itself a codec, dispatching to the built-in codec it names.
+///
+/// # Example
+///
+/// ```
+/// # use event_sourcing::codec::ContentType;
+///
+/// let content_type: ContentType = "application/msgpack".parse().expect("expected content type");
+///
+/// # assert_eq!(content_type, ContentType::MessagePack);
+/// # assert_eq!(content_type.to_string(), "application/msgpack");
+/// # assert_eq!(ContentType::default(), ContentType::Json);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ContentType {
+    #[default]
+    #[serde(rename = "application/json")]
+    Json,
+    #[serde(rename = "application/msgpack")]
+    MessagePack,
+    #[serde(rename = "application/cbor")]
+    Cbor,
+    #[serde(rename = "application/x-bincode")]
+    Bincode,
+}
+
+impl ContentType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentType::Json => "application/json",
+            ContentType::MessagePack => "application/msgpack",
+            ContentType::Cbor => "application/cbor",
+            ContentType::Bincode => "application/x-bincode",
+        }
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ContentType {
+    type Err = Error;
+
+    fn from_str(content_type: &str) -> Result<Self, Self::Err> {
+        [
+            ContentType::Json,
+            ContentType::MessagePack,
+            ContentType::Cbor,
+            ContentType::Bincode,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.as_str() == content_type)
+        .ok_or_else(|| Error::serialization(format!("unknown content type `{}`", content_type)))
+    }
+}
+
+/// Encodes values to, and decodes them from, bytes of a single content type.
+///
+/// The event and snapshot stores are generic over the codec they write with, so a codec for
+/// another format can be plugged in by implementing this trait.  Envelopes recorded with a content
+/// type other than the one of the store's codec are decoded with the built-in codec of their
+/// `ContentType`.
+///
+/// Self-describing codecs encode the JSON data model of a value, e.g. identifiers as strings, so
+/// their bytes can be decoded into a `serde_json::Value` for upcasting.
+///
+/// # Example
+///
+/// ```
+/// # use serde::de::DeserializeOwned;
+/// # use serde::Serialize;
+/// # use event_sourcing::codec::Codec;
+/// # use event_sourcing::Error;
+///
+/// #[derive(Debug, Clone, Copy, Default)]
+/// struct PrettyJsonCodec;
+///
+/// impl Codec for PrettyJsonCodec {
+///     fn content_type(&self) -> &str {
+///         "application/vnd.pretty+json"
+///     }
+///
+///     fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+///         Ok(serde_json::to_vec_pretty(value)?)
+///     }
+///
+///     fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+///         Ok(serde_json::from_slice(bytes)?)
+///     }
+/// }
+///
+/// let bytes = PrettyJsonCodec.encode(&vec![1, 2, 3]).expect("expected encoded value");
+/// let value: Vec<i64> = PrettyJsonCodec.decode(&bytes).expect("expected decoded value");
+///
+/// # assert!(PrettyJsonCodec.is_self_describing());
+/// # assert_eq!(value, vec![1, 2, 3]);
+/// ```
+pub trait Codec: Send + Sync + Clone {
+    // Content type recorded next to the encoded bytes.
+    fn content_type(&self) -> &str;
+    // Whether the encoded bytes can be decoded without knowing their Rust type.  Only
+    // self-describing content can be upcasted or decoded through an `EventRegistry`, and decoding
+    // other content fails when either is configured.
+    fn is_self_describing(&self) -> bool {
+        true
+    }
+    // Encode the value to bytes.
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error>;
+    // Decode a value from bytes produced by `encode`.
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error>;
+}
+
+/// JSON codec, used unless another content type is configured.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn content_type(&self) -> &str {
+        ContentType::Json.as_str()
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// MessagePack codec, encoding structs as maps keyed by field name.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePackCodec;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePackCodec {
+    fn content_type(&self) -> &str {
+        ContentType::MessagePack.as_str()
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        value
+            .serialize(
+                &mut rmp_serde::Serializer::new(&mut bytes)
+                    .with_struct_map()
+                    .with_human_readable(),
+            )
+            .map_err(Error::serialization)?;
+        Ok(bytes)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        T::deserialize(&mut rmp_serde::Deserializer::new(bytes).with_human_readable())
+            .map_err(Error::serialization)
+    }
+}
+
+/// CBOR codec, encoding values as they are so that byte strings and non-string map keys are kept.
+/// CBOR serializers are never human readable, so identifiers are encoded as byte strings, which the
+/// JSON data model cannot represent.  Like bincode, its bytes can only be decoded into the type they
+/// were encoded from, and decoding events fails when upcasters or an `EventRegistry` are configured.
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl Codec for CborCodec {
+    fn content_type(&self) -> &str {
+        ContentType::Cbor.as_str()
+    }
+
+    fn is_self_describing(&self) -> bool {
+        false
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).map_err(Error::serialization)?;
+        Ok(bytes)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        ciborium::from_reader::<T, _>(bytes).map_err(Error::serialization)
+    }
+}
+
+/// Bincode codec.  Bincode is not self-describing, so its bytes can only be decoded into the type
+/// they were encoded from.  Decoding events fails when upcasters or an `EventRegistry` are
+/// configured, since neither can be applied.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl Codec for BincodeCodec {
+    fn content_type(&self) -> &str {
+        ContentType::Bincode.as_str()
+    }
+
+    fn is_self_describing(&self) -> bool {
+        false
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        bincode::serialize(value).map_err(Error::serialization)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        bincode::deserialize(bytes).map_err(Error::serialization)
+    }
+}
+
+impl Codec for ContentType {
+    fn content_type(&self) -> &str {
+        self.as_str()
+    }
+
+    fn is_self_describing(&self) -> bool {
+        !matches!(self, ContentType::Cbor | ContentType::Bincode)
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        match *self {
+            ContentType::Json => JsonCodec.encode(value),
+            #[cfg(feature = "msgpack")]
+            ContentType::MessagePack => MessagePackCodec.encode(value),
+            #[cfg(feature = "cbor")]
+            ContentType::Cbor => CborCodec.encode(value),
+            #[cfg(feature = "bincode")]
+            ContentType::Bincode => BincodeCodec.encode(value),
+            #[allow(unreachable_patterns)]
+            content_type => Err(unsupported(content_type)),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        match *self {
+            ContentType::Json => JsonCodec.decode(bytes),
+            #[cfg(feature = "msgpack")]
+            ContentType::MessagePack => MessagePackCodec.decode(bytes),
+            #[cfg(feature = "cbor")]
+            ContentType::Cbor => CborCodec.decode(bytes),
+            #[cfg(feature = "bincode")]
+            ContentType::Bincode => BincodeCodec.decode(bytes),
+            #[allow(unreachable_patterns)]
+            content_type => Err(unsupported(content_type)),
+        }
+    }
+}
+
+// Error for a content type whose codec has not been enabled.
+#[allow(dead_code)]
+fn unsupported(content_type: ContentType) -> Error {
+    let feature = match content_type {
+        ContentType::Json => "default",
+        ContentType::MessagePack => "msgpack",
+        ContentType::Cbor => "cbor",
+        ContentType::Bincode => "bincode",
+    };
+    Error::serialization(format!(
+        "content type `{}` requires the `{}` feature",
+        content_type, feature
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    struct TestValue {
+        id: Uuid,
+        description: String,
+        timestamp: DateTime<Utc>,
+    }
+
+    fn test_value() -> TestValue {
+        TestValue {
+            id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
+            description: String::from("Deposit"),
+            timestamp: Utc::now(),
+        }
+    }
+
+    fn content_types() -> Vec<ContentType> {
+        let mut content_types = vec![ContentType::Json];
+        if cfg!(feature = "msgpack") {
+            content_types.push(ContentType::MessagePack);
+        }
+        if cfg!(feature = "cbor") {
+            content_types.push(ContentType::Cbor);
+        }
+        if cfg!(feature = "bincode") {
+            content_types.push(ContentType::Bincode);
+        }
+        content_types
+    }
+
+    #[test]
+    fn it_round_trips_every_enabled_content_type() {
+        for content_type in content_types() {
+            let bytes = content_type
+                .encode(&test_value())
+                .expect("expected encoded value");
+            let value: TestValue = content_type.decode(&bytes).expect("expected decoded value");
+            assert_eq!(value.description, "Deposit");
+            if content_type.is_self_describing() {
+                let value: serde_json::Value = content_type
+                    .decode(&bytes)
+                    .expect("expected decoded JSON value");
+                assert_eq!(value["id"], "2e996ba1-03a6-47af-8fd1-2039c6708dd4");
+            }
+        }
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn it_keeps_non_string_map_keys_in_cbor() {
+        let bytes = CborCodec
+            .encode(&std::collections::BTreeMap::from([(1, "Deposit")]))
+            .expect("expected encoded value");
+        let value: ciborium::Value = CborCodec.decode(&bytes).expect("expected decoded value");
+        assert_eq!(
+            value,
+            ciborium::Value::Map(vec![(
+                ciborium::Value::Integer(1.into()),
+                ciborium::Value::Text(String::from("Deposit"))
+            )])
+        );
+    }
+
+    #[test]
+    fn it_parses_recorded_content_types() {
+        for content_type in [
+            ContentType::Json,
+            ContentType::MessagePack,
+            ContentType::Cbor,
+            ContentType::Bincode,
+        ] {
+            assert_eq!(
+                content_type.as_str().parse::<ContentType>().ok(),
+                Some(content_type)
+            );
+        }
+        assert!("text/plain".parse::<ContentType>().is_err());
+    }
+}
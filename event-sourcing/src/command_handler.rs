@@ -1,9 +1,11 @@
+pub mod aggregate;
+
 #[async_trait::async_trait]
-pub trait CommandHandler<Command>
+pub trait CommandHandler<Command, Response = ()>
 where
     Command: Send + Sync,
 {
     type Error: Send + Sync;
 
-    async fn handle(&self, command: Command) -> Result<(), Self::Error>;
+    async fn handle(&self, command: Command) -> Result<Response, Self::Error>;
 }
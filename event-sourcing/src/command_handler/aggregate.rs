@@ -0,0 +1,361 @@
+use uuid::Uuid;
+
+use crate::aggregate::repository::AggregateRepository;
+use crate::aggregate::Decider;
+use crate::command_handler::CommandHandler;
+use crate::event::metadata::EventMetadata;
+use crate::event::store::EventStore;
+use crate::snapshot::store::{NoSnapshotStore, SnapshotStore};
+use crate::Error;
+
+/// Outcome of a command that has been handled successfully.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandResult<Event> {
+    // Version of the aggregate after the events have been persisted.
+    pub version: i64,
+    // Events produced by the command.
+    pub events: Vec<Event>,
+}
+
+/// Command handler that drives a `Decider` aggregate end to end.
+///
+/// The aggregate is loaded through the repository, the command is decided against its current state
+/// and the resulting events are persisted at the loaded version.  When another writer modified the
+/// aggregate in the meantime the command is retried against the fresh state, up to `max_retries`
+/// times.
+///
+/// Every event is recorded with the metadata extracted from the command.  When the command does not
+/// provide a causation or correlation id, a fresh id identifying the command is used for both.
+///
+/// # Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use uuid::Uuid;
+/// # use event_sourcing::Error;
+/// # use serde::{Deserialize, Serialize};
+/// # use event_sourcing::aggregate::{Aggregate, Decider};
+/// # use event_sourcing::aggregate::repository::AggregateRepository;
+/// # use event_sourcing::command_handler::CommandHandler;
+/// # use event_sourcing::command_handler::aggregate::AggregateCommandHandler;
+/// # use event_sourcing::event::EventType;
+/// # use event_sourcing::event::store::in_memory::InMemoryEventStore;
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     id: Uuid,
+/// #     amount: i64,
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// # }
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize)]
+/// # struct TestAggregate {
+/// #     id: Uuid,
+/// #     total: i64,
+/// # }
+///
+/// # impl Aggregate for TestAggregate {
+/// #     type AggregateID = Uuid;
+/// #     type Event = TestEvent;
+/// #     type Error = Error;
+/// #
+/// #     fn aggregate_type() -> String {
+/// #         String::from("TestAggregate")
+/// #     }
+/// #
+/// #     fn aggregate_id(&self) -> &Self::AggregateID {
+/// #         &self.id
+/// #     }
+/// #
+/// #     fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error> {
+/// #         match state {
+/// #             None => Ok(Self { id: event.id, total: event.amount }),
+/// #             Some(mut state) => {
+/// #                 state.total += event.amount;
+/// #                 Ok(state)
+/// #             }
+/// #         }
+/// #     }
+/// #
+/// #     fn apply_all(events: Vec<Self::Event>) -> Result<Self, Self::Error> {
+/// #         events
+/// #             .into_iter()
+/// #             .try_fold(None, |state, event| Self::apply(state, event).map(Some))?
+/// #             .ok_or_else(|| Error::from("Aggregate must not be None"))
+/// #     }
+/// # }
+///
+/// # #[derive(Debug, Clone)]
+/// # struct Deposit {
+/// #     id: Uuid,
+/// #     amount: i64,
+/// # }
+///
+/// # impl Decider for TestAggregate {
+/// #     type Command = Deposit;
+/// #
+/// #     fn decide(_state: Option<&Self>, command: Self::Command) -> Result<Vec<Self::Event>, Self::Error> {
+/// #         Ok(vec![TestEvent { id: command.id, amount: command.amount, description: String::from("Deposit") }])
+/// #     }
+/// # }
+///
+/// # futures::executor::block_on(async {
+/// # let id = Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid");
+/// let command_handler = AggregateCommandHandler::new(
+///     AggregateRepository::<TestAggregate, _>::new(InMemoryEventStore::default()),
+///     |command: &Deposit| command.id,
+/// )
+/// .with_max_retries(5);
+/// let command_result = command_handler
+///     .handle(Deposit { id, amount: 1 })
+///     .await
+///     .expect("expected handled command");
+///
+/// # assert_eq!(command_result.version, 1);
+/// # assert_eq!(command_result.events, vec![TestEvent { id, amount: 1, description: String::from("Deposit") }]);
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct AggregateCommandHandler<A, E, S = NoSnapshotStore>
+where
+    A: Decider,
+    E: EventStore,
+    S: SnapshotStore,
+{
+    repository: AggregateRepository<A, E, S>,
+    aggregate_id: fn(&A::Command) -> A::AggregateID,
+    metadata: fn(&A::Command) -> EventMetadata,
+    max_retries: usize,
+}
+
+impl<A, E, S> AggregateCommandHandler<A, E, S>
+where
+    A: Decider,
+    E: EventStore,
+    S: SnapshotStore,
+{
+    /// Create a handler that retries up to three times on version conflicts.
+    pub fn new(
+        repository: AggregateRepository<A, E, S>,
+        aggregate_id: fn(&A::Command) -> A::AggregateID,
+    ) -> Self {
+        Self {
+            repository,
+            aggregate_id,
+            metadata: |_| EventMetadata::default(),
+            max_retries: 3,
+        }
+    }
+
+    /// Set how the correlation id, causation id, principal and headers are extracted from a command.
+    pub fn with_metadata(mut self, metadata: fn(&A::Command) -> EventMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Set how many times a command is retried after a version conflict.
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<A, E, S> CommandHandler<A::Command, CommandResult<A::Event>>
+    for AggregateCommandHandler<A, E, S>
+where
+    A: Decider + 'static,
+    A::AggregateID: ToString,
+    A::Command: Clone,
+    E: EventStore,
+    S: SnapshotStore + 'static,
+    Error: From<A::Error>,
+{
+    type Error = Error;
+
+    async fn handle(&self, command: A::Command) -> Result<CommandResult<A::Event>, Self::Error> {
+        let aggregate_id = (self.aggregate_id)(&command);
+        let command_id = Uuid::new_v4();
+        let metadata = (self.metadata)(&command);
+        let metadata = EventMetadata {
+            correlation_id: metadata.correlation_id.or(Some(command_id)),
+            causation_id: metadata.causation_id.or(Some(command_id)),
+            ..metadata
+        };
+        let mut retries = 0;
+        loop {
+            let aggregate = self.repository.load_aggregate(&aggregate_id).await?;
+            let events = A::decide(aggregate.state.as_ref(), command.clone())?;
+            match self
+                .repository
+                .commit(&aggregate_id, aggregate, events.clone(), metadata.clone())
+                .await
+            {
+                Ok(version) => return Ok(CommandResult { version, events }),
+                Err(Error::VersionConflict(_)) if retries < self.max_retries => retries += 1,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::envelope::EventEnvelope;
+    use crate::event::store::in_memory::InMemoryEventStore;
+    use crate::event::store::ExpectedVersion;
+    use crate::fixtures::{
+        test_event, test_id, ConflictingEventStore, TestAggregate, TestCommand, TestEvent,
+    };
+
+    // Handler for an aggregate holding a balance of 10, along with the store it writes to.  Another
+    // writer gets in ahead of the next `conflicts` appends.
+    async fn command_handler(
+        conflicts: usize,
+    ) -> (
+        AggregateCommandHandler<TestAggregate, ConflictingEventStore>,
+        ConflictingEventStore,
+    ) {
+        let event_store = InMemoryEventStore::default();
+        let repository: AggregateRepository<TestAggregate, _> =
+            AggregateRepository::new(event_store.clone());
+        repository
+            .save(&test_id(), vec![test_event(10)], ExpectedVersion::NoStream)
+            .await
+            .expect("expected saved events");
+        let event_store = ConflictingEventStore::new(event_store, conflicts);
+        (
+            AggregateCommandHandler::new(
+                AggregateRepository::new(event_store.clone()),
+                TestCommand::id,
+            ),
+            event_store,
+        )
+    }
+
+    #[tokio::test]
+    async fn it_handles_command() {
+        let (command_handler, _) = command_handler(0).await;
+        let command_result = command_handler
+            .handle(TestCommand::Withdraw {
+                id: test_id(),
+                amount: 4,
+            })
+            .await
+            .expect("expected handled command");
+        assert_eq!(
+            command_result,
+            CommandResult {
+                version: 2,
+                events: vec![TestEvent {
+                    id: test_id(),
+                    amount: -4,
+                    description: String::from("Withdrawal"),
+                }],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_command() {
+        let (command_handler, _) = command_handler(0).await;
+        command_handler
+            .handle(TestCommand::Withdraw {
+                id: test_id(),
+                amount: 11,
+            })
+            .await
+            .expect_err("expected rejected command");
+    }
+
+    #[tokio::test]
+    async fn it_retries_on_version_conflict() {
+        let (command_handler, event_store) = command_handler(2).await;
+        let command_result = command_handler
+            .handle(TestCommand::Withdraw {
+                id: test_id(),
+                amount: 3,
+            })
+            .await
+            .expect("expected handled command");
+        assert_eq!(command_result.version, 4);
+        assert_eq!(event_store.appends(), 3);
+        // Both withdrawals of the other writer were stored before the retried one.
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&test_id().to_string())
+            .await
+            .expect("expected events");
+        assert_eq!(
+            event_envelopes
+                .iter()
+                .map(|event_envelope| event_envelope.data.amount)
+                .collect::<Vec<i64>>(),
+            vec![10, -3, -3, -3]
+        );
+        let (state, _) = command_handler
+            .repository
+            .load(&test_id())
+            .await
+            .expect("expected aggregate");
+        assert_eq!(state.expect("expected aggregate").total, 1);
+    }
+
+    #[tokio::test]
+    async fn it_gives_up_after_max_retries() {
+        let (command_handler, event_store) = command_handler(3).await;
+        let command_handler = command_handler.with_max_retries(1);
+        let error = command_handler
+            .handle(TestCommand::Withdraw {
+                id: test_id(),
+                amount: 4,
+            })
+            .await
+            .expect_err("expected version conflict");
+        assert!(matches!(error, Error::VersionConflict(_)));
+        // The first attempt and a single retry were both rejected by the store.
+        assert_eq!(event_store.appends(), 2);
+        assert_eq!(
+            event_store
+                .read_version(&test_id().to_string())
+                .await
+                .expect("expected version"),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn it_records_metadata_of_the_command() {
+        let event_store = InMemoryEventStore::default();
+        let repository: AggregateRepository<TestAggregate, _> =
+            AggregateRepository::new(event_store.clone());
+        repository
+            .save(&test_id(), vec![test_event(10)], ExpectedVersion::NoStream)
+            .await
+            .expect("expected saved events");
+        let command_handler = AggregateCommandHandler::new(repository, TestCommand::id)
+            .with_metadata(|_| EventMetadata::default().with_principal("teller"));
+        command_handler
+            .handle(TestCommand::Withdraw {
+                id: test_id(),
+                amount: 4,
+            })
+            .await
+            .expect("expected handled command");
+
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read_from(&test_id().to_string(), 2)
+            .await
+            .expect("expected events");
+        let metadata = &event_envelopes[0].metadata;
+        assert_eq!(metadata.principal, Some(String::from("teller")));
+        assert!(metadata.causation_id.is_some());
+        assert_eq!(metadata.correlation_id, metadata.causation_id);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::event::store::VersionConflictError;
+
+/// Boxed error used as the source of backend and domain failures.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Error returned by the stores, listeners and handlers of this crate.
+///
+/// Backend crates convert their own errors into the variant that best describes the failure, so
+/// callers can decide whether to retry, reload or alert without inspecting messages.
+///
+/// # Example
+///
+/// ```
+/// # use event_sourcing::Error;
+/// # use event_sourcing::event::store::{ExpectedVersion, VersionConflictError};
+///
+/// let error: Error = VersionConflictError::new(String::from("aggregate_id"), ExpectedVersion::NoStream, 1).into();
+///
+/// match error {
+///     Error::VersionConflict(conflict) => assert_eq!(conflict.actual_version, 1),
+///     _ => panic!("expected version conflict"),
+/// }
+/// ```
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // The requested aggregate, stream or snapshot does not exist.
+    #[error("`{0}` was not found")]
+    NotFound(String),
+    // The aggregate was modified concurrently, reload it and retry.
+    #[error(transparent)]
+    VersionConflict(#[from] VersionConflictError),
+    // The stream of the aggregate has been deleted and cannot be written to anymore.
+    #[error("stream `{0}` has been deleted")]
+    StreamDeleted(String),
+    // An envelope or its data could not be serialized or deserialized.
+    #[error("serialization error: {0}")]
+    Serialization(#[source] BoxError),
+    // An envelope has an event type that is not in the registry of the event.
+    #[error("unknown event type `{0}`")]
+    UnknownEventType(String),
+    // The operation was called with arguments it cannot accept.
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    // A backend failure that may succeed when retried, e.g. a timeout or an unavailable node.
+    #[error("transient backend error: {0}")]
+    Transient(#[source] BoxError),
+    // A backend failure that will not succeed when retried.
+    #[error("backend error: {0}")]
+    Backend(#[source] BoxError),
+    // Any other failure, e.g. raised by an aggregate or an event handler.
+    #[error("{0}")]
+    Other(#[source] BoxError),
+}
+
+impl Error {
+    /// Create a serialization error.
+    pub fn serialization(error: impl Into<BoxError>) -> Self {
+        Error::Serialization(error.into())
+    }
+
+    /// Create a backend error that may succeed when retried.
+    pub fn transient(error: impl Into<BoxError>) -> Self {
+        Error::Transient(error.into())
+    }
+
+    /// Create a backend error that will not succeed when retried.
+    pub fn backend(error: impl Into<BoxError>) -> Self {
+        Error::Backend(error.into())
+    }
+
+    /// Whether the operation may succeed when retried.  Version conflicts are retryable once the
+    /// aggregate has been reloaded.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Error::VersionConflict(_) | Error::Transient(_))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Serialization(error.into())
+    }
+}
+
+impl From<&str> for Error {
+    fn from(error: &str) -> Self {
+        Error::Other(error.into())
+    }
+}
+
+impl From<String> for Error {
+    fn from(error: String) -> Self {
+        Error::Other(error.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+    use crate::event::store::ExpectedVersion;
+
+    #[test]
+    fn it_classifies_retryable_errors() {
+        let version_conflict: Error =
+            VersionConflictError::new(String::from("aggregate_id"), ExpectedVersion::Exact(1), 2)
+                .into();
+        assert!(version_conflict.is_retryable());
+        assert!(Error::transient("timeout").is_retryable());
+        assert!(!Error::backend("unauthorized").is_retryable());
+        assert!(!Error::NotFound(String::from("aggregate_id")).is_retryable());
+    }
+
+    #[test]
+    fn it_converts_serde_errors() {
+        let error: Error = serde_json::from_str::<i64>("not a number")
+            .expect_err("expected serde error")
+            .into();
+        assert!(matches!(error, Error::Serialization(_)));
+        assert!(error.source().is_some());
+    }
+}
@@ -1,6 +1,11 @@
 pub mod envelope;
 pub mod listener;
+pub mod metadata;
+pub mod registry;
 pub mod store;
+pub mod upcaster;
+
+use crate::event::registry::EventRegistry;
 
 /// Trait to determine the type of the event.
 ///
@@ -10,8 +15,10 @@ pub mod store;
 /// # use uuid::Uuid;
 /// # use event_sourcing::event::EventType;
 ///
-/// #[derive(Debug, Clone, Copy)]
-/// struct TestEvent;
+/// #[derive(Debug, Clone)]
+/// struct TestEvent {
+///     description: String,
+/// }
 ///
 /// impl EventType for TestEvent {
 ///     fn event_type(&self) -> String {
@@ -19,8 +26,19 @@ pub mod store;
 ///     }
 /// }
 ///
-/// # assert_eq!(TestEvent.event_type(), String::from("TestEvent"));
+/// # let test_event = TestEvent { description: String::from("Deposit") };
+/// # assert_eq!(test_event.event_type(), String::from("TestEvent"));
 /// ```
-pub trait EventType: Send + Sync + Clone + Copy {
+pub trait EventType: Send + Sync + Clone + 'static {
     fn event_type(&self) -> String;
-}
\ No newline at end of file
+    // Version of the shape of the event.  Bump it whenever the shape changes and register an
+    // upcaster that moves the previous shape to the new one.
+    fn schema_version(&self) -> i64 {
+        1
+    }
+    // Registry used to decode the data of an envelope based on its `event_type`, or `None` to decode
+    // it with the `Deserialize` implementation of the event.
+    fn registry() -> Option<&'static EventRegistry<Self>> {
+        None
+    }
+}
@@ -1,20 +1,25 @@
 use chrono::{DateTime, Utc};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use uuid::Uuid;
 
-use crate::Error;
+use crate::codec::{Codec, JsonCodec};
+use crate::event::metadata::EventMetadata;
+use crate::event::registry::deserialize_data;
+use crate::event::upcaster::UpcasterRegistry;
 use crate::event::EventType;
+use crate::Error;
 
 /// Event is a domain envelope describing a change that has happened to an aggregate.
 ///
-/// As of now, I recommend that you mark your Event enum with `#[serde(tag = "internal_event_type")]`
-/// so that it deserializes with the correct type when there are variants.  This approach is to be
-/// deprecated in the near future.
+/// The data of the envelope is decoded with the `Deserialize` implementation of the event, or based
+/// on the `event_type` of the envelope when the event provides an `EventRegistry`, which lets event
+/// enums be stored without a serde tag.
 #[derive(Debug, Clone, Serialize, Deserialize, derive_new::new)]
 pub struct EventEnvelope<Event>
-    where
-        Event: EventType + Serialize,
+where
+    Event: EventType + Serialize,
 {
     // Unique identifier of the envelope.
     #[new(value = "Uuid::new_v4()")]
@@ -23,6 +28,11 @@ pub struct EventEnvelope<Event>
     pub aggregate_id: String,
     // Type of the aggregate that the envelope can be applied to.
     pub aggregate_type: String,
+    // Version of the shape of the event.  Envelopes written before schema versions existed are at
+    // version 1.
+    #[new(value = "data.schema_version()")]
+    #[serde(default = "default_schema_version")]
+    pub schema_version: i64,
     // Event attached to the envelope.
     pub data: Event,
     // Type of the envelope.
@@ -32,6 +42,31 @@ pub struct EventEnvelope<Event>
     // Timestamp of when the envelope was created.
     #[new(value = "Utc::now()")]
     pub timestamp: DateTime<Utc>,
+    // Correlation, causation and principal of the envelope.  Envelopes written before metadata
+    // existed deserialize with empty metadata.
+    #[new(default)]
+    #[serde(default)]
+    pub metadata: EventMetadata,
+    // Position of the envelope in the global order of every event in the store, assigned when it
+    // is stored.  It is `0` until then, and for envelopes written before positions existed.
+    #[new(default)]
+    #[serde(default)]
+    pub position: i64,
+}
+
+impl<Event> EventEnvelope<Event>
+where
+    Event: EventType + Serialize,
+{
+    /// Attach metadata to the envelope.
+    pub fn with_metadata(mut self, metadata: EventMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+fn default_schema_version() -> i64 {
+    1
 }
 
 /// Serialize the Event Envelope struct to a string.
@@ -45,10 +80,11 @@ pub struct EventEnvelope<Event>
 /// # use event_sourcing::event::envelope::{EventEnvelope, serialize};
 /// # use event_sourcing::event::EventType;
 ///
-/// # #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 /// # struct TestEvent {
 /// #     id: Uuid,
 /// #     amount: i64,
+/// #     description: String,
 /// # }
 ///
 /// # impl EventType for TestEvent {
@@ -60,6 +96,7 @@ pub struct EventEnvelope<Event>
 /// # let test_event = TestEvent {
 /// #     id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
 /// #     amount: 1,
+/// #     description: String::from("Deposit"),
 /// # };
 /// # let event_envelope: EventEnvelope<TestEvent> = EventEnvelope::new(
 /// #     String::from("aggregate_id"),
@@ -90,10 +127,11 @@ pub fn serialize<Event: EventType + Serialize + DeserializeOwned>(
 /// # use event_sourcing::event::envelope::{deserialize, EventEnvelope};
 /// # use event_sourcing::event::EventType;
 ///
-/// # #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 /// # struct TestEvent {
 /// #     id: Uuid,
 /// #     amount: i64,
+/// #     description: String,
 /// # }
 ///
 /// # impl EventType for TestEvent {
@@ -102,7 +140,7 @@ pub fn serialize<Event: EventType + Serialize + DeserializeOwned>(
 /// #     }
 /// # }
 ///
-/// # let json_event_envelope: String = String::from("{\"id\":\"17401eba-ff5d-4c3c-9818-c603fe640cb5\",\"aggregate_id\":\"aggregate_id\",\"aggregate_type\":\"TestAggregate\",\"data\":{\"id\":\"2e996ba1-03a6-47af-8fd1-2039c6708dd4\",\"amount\":1},\"event_type\":\"TestEvent\",\"version\":0,\"timestamp\":\"2022-12-28T03:52:22.782613772Z\"}");
+/// # let json_event_envelope: String = String::from("{\"id\":\"17401eba-ff5d-4c3c-9818-c603fe640cb5\",\"aggregate_id\":\"aggregate_id\",\"aggregate_type\":\"TestAggregate\",\"data\":{\"id\":\"2e996ba1-03a6-47af-8fd1-2039c6708dd4\",\"amount\":1,\"description\":\"Deposit\"},\"event_type\":\"TestEvent\",\"version\":0,\"timestamp\":\"2022-12-28T03:52:22.782613772Z\"}");
 /// let event_envelope: EventEnvelope<TestEvent> = deserialize(json_event_envelope).expect("expected deserialized struct");
 ///
 /// # assert_eq!(event_envelope.aggregate_id, String::from("aggregate_id"));
@@ -112,43 +150,230 @@ pub fn serialize<Event: EventType + Serialize + DeserializeOwned>(
 /// # assert_eq!(event_envelope.data, TestEvent {
 /// #     id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
 /// #     amount: 1,
+/// #     description: String::from("Deposit"),
 /// # });
 /// ```
 pub fn deserialize<Event: EventType + Serialize + DeserializeOwned>(
     event_envelope: String,
 ) -> Result<EventEnvelope<Event>, Error> {
-    serde_json::from_str(event_envelope.as_str()).map_err(|error| error.into())
+    let event_envelope: Value = serde_json::from_str(event_envelope.as_str())?;
+    let event_type = event_envelope["event_type"].as_str().map(String::from);
+    from_value(event_envelope)?
+        .ok_or_else(|| Error::UnknownEventType(event_type.unwrap_or_default()))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::str::FromStr;
+/// Deserialize a string Event Envelope to a struct, upcasting its data to the current shape of the
+/// event first.  Returns `None` when the event type is not in the registry of the event and the
+/// registry skips unknown event types.
+///
+/// # Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use uuid::Uuid;
+/// # use serde::{Deserialize, Serialize};
+/// # use serde_json::Value;
+/// # use event_sourcing::Error;
+/// # use event_sourcing::event::envelope::{deserialize_with_upcasters, EventEnvelope};
+/// # use event_sourcing::event::upcaster::UpcasterRegistry;
+/// # use event_sourcing::event::EventType;
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     id: Uuid,
+/// #     amount: i64,
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// #
+/// #     fn schema_version(&self) -> i64 {
+/// #         2
+/// #     }
+/// # }
+///
+/// fn add_description(mut data: Value) -> Result<Value, Error> {
+///     data["description"] = Value::from("Deposit");
+///     Ok(data)
+/// }
+///
+/// # let json_event_envelope: String = String::from("{\"id\":\"17401eba-ff5d-4c3c-9818-c603fe640cb5\",\"aggregate_id\":\"aggregate_id\",\"aggregate_type\":\"TestAggregate\",\"data\":{\"id\":\"2e996ba1-03a6-47af-8fd1-2039c6708dd4\",\"amount\":1},\"event_type\":\"TestEvent\",\"version\":1,\"timestamp\":\"2022-12-28T03:52:22.782613772Z\"}");
+/// let upcasters = UpcasterRegistry::default().register("TestEvent", 1, add_description);
+/// let event_envelope: EventEnvelope<TestEvent> = deserialize_with_upcasters(json_event_envelope, &upcasters)
+///     .expect("expected deserialized struct")
+///     .expect("expected known event type");
+///
+/// # assert_eq!(event_envelope.schema_version, 2);
+/// # assert_eq!(event_envelope.data.description, String::from("Deposit"));
+/// ```
+pub fn deserialize_with_upcasters<Event: EventType + Serialize + DeserializeOwned>(
+    event_envelope: String,
+    upcasters: &UpcasterRegistry,
+) -> Result<Option<EventEnvelope<Event>>, Error> {
+    decode(&JsonCodec, event_envelope.as_bytes(), upcasters)
+}
+
+/// Encode the Event Envelope struct to bytes with the given codec.
+///
+/// # Example
+///
+/// ```
+/// # use serde::{Deserialize, Serialize};
+/// # use event_sourcing::codec::JsonCodec;
+/// # use event_sourcing::event::envelope::{decode, encode, EventEnvelope};
+/// # use event_sourcing::event::upcaster::UpcasterRegistry;
+/// # use event_sourcing::event::EventType;
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// # }
+///
+/// # let event_envelope: EventEnvelope<TestEvent> = EventEnvelope::new(
+/// #     String::from("aggregate_id"),
+/// #     String::from("TestAggregate"),
+/// #     TestEvent { description: String::from("Deposit") },
+/// #     String::from("TestEvent"),
+/// #     1,
+/// # );
+/// let bytes = encode(&event_envelope, &JsonCodec).expect("expected encoded struct");
+/// let decoded_event_envelope: EventEnvelope<TestEvent> =
+///     decode(&JsonCodec, &bytes, &UpcasterRegistry::default())
+///         .expect("expected decoded struct")
+///         .expect("expected known event type");
+///
+/// # assert_eq!(decoded_event_envelope.id, event_envelope.id);
+/// # assert_eq!(decoded_event_envelope.data, event_envelope.data);
+/// ```
+pub fn encode<Event: EventType + Serialize + DeserializeOwned, C: Codec>(
+    event_envelope: &EventEnvelope<Event>,
+    codec: &C,
+) -> Result<Vec<u8>, Error> {
+    codec.encode(event_envelope)
+}
 
-    #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
-    struct TestEvent {
-        id: Uuid,
-        amount: i64,
+/// Decode bytes encoded with the given codec to an Event Envelope struct.  Self-describing content
+/// is upcasted and decoded through the registry of the event, so `None` is returned when the event
+/// type is not registered and the registry skips unknown event types.  Other content is decoded as
+/// it is, and fails with `Error::InvalidArgument` when upcasters or a registry are configured.
+pub fn decode<Event: EventType + Serialize + DeserializeOwned, C: Codec>(
+    codec: &C,
+    event_envelope: &[u8],
+    upcasters: &UpcasterRegistry,
+) -> Result<Option<EventEnvelope<Event>>, Error> {
+    if !codec.is_self_describing() {
+        check_codec::<Event, C>(codec, upcasters)?;
+        return Ok(Some(codec.decode(event_envelope)?));
     }
+    decode_value(codec.decode(event_envelope)?, upcasters)
+}
 
-    impl EventType for TestEvent {
-        fn event_type(&self) -> String {
-            String::from("TestEvent")
-        }
+/// Fail with `Error::InvalidArgument` when events decoded with a codec that is not self-describing
+/// would have to be upcasted or decoded through the registry of the event.  Both work on the JSON
+/// data model of an event, which such content does not carry.
+pub fn check_codec<Event: EventType, C: Codec>(
+    codec: &C,
+    upcasters: &UpcasterRegistry,
+) -> Result<(), Error> {
+    if codec.is_self_describing() {
+        return Ok(());
+    }
+    if !upcasters.is_empty() {
+        return Err(Error::InvalidArgument(format!(
+            "events encoded as `{}` cannot be upcasted",
+            codec.content_type()
+        )));
+    }
+    if Event::registry().is_some() {
+        return Err(Error::InvalidArgument(format!(
+            "events encoded as `{}` cannot be decoded through an event registry",
+            codec.content_type()
+        )));
     }
+    Ok(())
+}
+
+/// Decode an Event Envelope that has already been parsed to JSON, upcasting its data first.
+pub fn decode_value<Event: EventType + Serialize + DeserializeOwned>(
+    event_envelope: Value,
+    upcasters: &UpcasterRegistry,
+) -> Result<Option<EventEnvelope<Event>>, Error> {
+    from_value(upcasters.upcast_envelope(event_envelope)?)
+}
+
+// Decode a JSON envelope, decoding its data with the registry of the event when it has one.
+fn from_value<Event: EventType + Serialize + DeserializeOwned>(
+    mut event_envelope: Value,
+) -> Result<Option<EventEnvelope<Event>>, Error> {
+    if Event::registry().is_none() {
+        return Ok(Some(serde_json::from_value(event_envelope)?));
+    }
+    let event_type = event_envelope["event_type"]
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| Error::serialization("event envelope is missing its `event_type`"))?;
+    let data = match deserialize_data(&event_type, event_envelope["data"].take())? {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+    let event_envelope: EventEnvelope<Undecoded> = serde_json::from_value(event_envelope)?;
+    Ok(Some(EventEnvelope {
+        id: event_envelope.id,
+        aggregate_id: event_envelope.aggregate_id,
+        aggregate_type: event_envelope.aggregate_type,
+        schema_version: event_envelope.schema_version,
+        data,
+        event_type: event_envelope.event_type,
+        version: event_envelope.version,
+        timestamp: event_envelope.timestamp,
+        metadata: event_envelope.metadata,
+        position: event_envelope.position,
+    }))
+}
+
+// Stand-in for data that has been taken out of an envelope to be decoded separately.
+#[derive(Clone, Serialize, Deserialize)]
+struct Undecoded;
+
+impl EventType for Undecoded {
+    fn event_type(&self) -> String {
+        String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::ContentType;
+    use crate::fixtures::{test_event, TestEvent};
+    use std::str::FromStr;
 
     #[test]
     fn it_serializes_and_deserializes() {
-        let test_event = TestEvent {
-            id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
-            amount: 1,
-        };
+        let event = test_event(1);
+        let event_type = event.event_type();
         let event_envelope: EventEnvelope<TestEvent> = EventEnvelope::new(
             String::from("aggregate_id"),
             String::from("TestAggregate"),
-            test_event,
-            test_event.event_type(),
+            event,
+            event_type,
             0,
+        )
+        .with_metadata(
+            EventMetadata::default()
+                .with_correlation_id(
+                    Uuid::from_str("17401eba-ff5d-4c3c-9818-c603fe640cb5").expect("expected uuid"),
+                )
+                .with_principal("user@example.com"),
         );
         let serialized_event_envelope: String =
             serialize(&event_envelope).expect("expected serialized struct");
@@ -156,17 +381,96 @@ mod tests {
             deserialize(serialized_event_envelope).expect("expected deserialized struct");
         assert_eq!(event_envelope.aggregate_id, String::from("aggregate_id"));
         assert_eq!(event_envelope.aggregate_type, String::from("TestAggregate"));
+        assert_eq!(event_envelope.event_type, String::from("TestEvent"));
+        assert_eq!(event_envelope.version, 0);
+        assert_eq!(event_envelope.data, test_event(1));
         assert_eq!(
-            event_envelope.event_type,
-            String::from("TestEvent")
+            event_envelope.metadata.correlation_id,
+            Some(Uuid::from_str("17401eba-ff5d-4c3c-9818-c603fe640cb5").expect("expected uuid"))
         );
-        assert_eq!(event_envelope.version, 0);
         assert_eq!(
-            event_envelope.data,
-            TestEvent {
-                id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
-                amount: 1,
+            event_envelope.metadata.principal,
+            Some(String::from("user@example.com"))
+        );
+    }
+
+    #[test]
+    fn it_encodes_and_decodes_every_enabled_content_type() {
+        let mut content_types = vec![ContentType::Json];
+        if cfg!(feature = "msgpack") {
+            content_types.push(ContentType::MessagePack);
+        }
+        if cfg!(feature = "cbor") {
+            content_types.push(ContentType::Cbor);
+        }
+        if cfg!(feature = "bincode") {
+            content_types.push(ContentType::Bincode);
+        }
+        for content_type in content_types {
+            let event = test_event(1);
+            let event_type = event.event_type();
+            let event_envelope = EventEnvelope::new(
+                String::from("aggregate_id"),
+                String::from("TestAggregate"),
+                event,
+                event_type,
+                1,
+            )
+            .with_metadata(EventMetadata::default().with_principal("user@example.com"));
+            let bytes = encode(&event_envelope, &content_type).expect("expected encoded struct");
+            let decoded_event_envelope: EventEnvelope<TestEvent> =
+                decode(&content_type, &bytes, &UpcasterRegistry::default())
+                    .expect("expected decoded struct")
+                    .expect("expected known event type");
+            assert_eq!(decoded_event_envelope.id, event_envelope.id);
+            assert_eq!(decoded_event_envelope.data, event_envelope.data);
+            assert_eq!(decoded_event_envelope.timestamp, event_envelope.timestamp);
+            assert_eq!(decoded_event_envelope.metadata, event_envelope.metadata);
+        }
+    }
+
+    #[test]
+    fn it_rejects_upcasters_for_content_that_is_not_self_describing() {
+        // JSON treated as opaque bytes, like the encoding of a schema-less binary format.
+        #[derive(Clone)]
+        struct OpaqueCodec;
+
+        impl Codec for OpaqueCodec {
+            fn content_type(&self) -> &str {
+                "application/x-opaque"
+            }
+
+            fn is_self_describing(&self) -> bool {
+                false
             }
+
+            fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+                ContentType::Json.encode(value)
+            }
+
+            fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+                ContentType::Json.decode(bytes)
+            }
+        }
+
+        let event = test_event(1);
+        let event_type = event.event_type();
+        let event_envelope = EventEnvelope::new(
+            String::from("aggregate_id"),
+            String::from("TestAggregate"),
+            event,
+            event_type,
+            1,
         );
+        let bytes = encode(&event_envelope, &OpaqueCodec).expect("expected encoded struct");
+        let decoded_event_envelope: Option<EventEnvelope<TestEvent>> =
+            decode(&OpaqueCodec, &bytes, &UpcasterRegistry::default())
+                .expect("expected decoded struct");
+        assert!(decoded_event_envelope.is_some());
+
+        let upcasters = UpcasterRegistry::default().register("TestEvent", 1, Ok);
+        let error = decode::<TestEvent, _>(&OpaqueCodec, &bytes, &upcasters)
+            .expect_err("expected rejected upcasters");
+        assert!(matches!(error, Error::InvalidArgument(_)));
     }
 }
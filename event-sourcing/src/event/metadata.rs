@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::event::envelope::EventEnvelope;
+use crate::event::EventType;
+
+/// Metadata describing why, and on whose behalf, an event has been recorded.
+///
+/// Every event of a business transaction shares the same correlation id, while the causation id
+/// points at the command or event that directly caused it.
+///
+/// # Example
+///
+/// ```
+/// # use uuid::Uuid;
+/// # use event_sourcing::event::metadata::EventMetadata;
+///
+/// let correlation_id = Uuid::new_v4();
+/// let metadata = EventMetadata::default()
+///     .with_correlation_id(correlation_id)
+///     .with_principal("user@example.com")
+///     .with_header("client", "web");
+///
+/// # assert_eq!(metadata.correlation_id, Some(correlation_id));
+/// # assert_eq!(metadata.causation_id, None);
+/// # assert_eq!(metadata.principal, Some(String::from("user@example.com")));
+/// # assert_eq!(metadata.headers.get("client"), Some(&String::from("web")));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventMetadata {
+    // Identifier shared by every command and event of the same business transaction.
+    pub correlation_id: Option<Uuid>,
+    // Identifier of the command or event that directly caused the event.
+    pub causation_id: Option<Uuid>,
+    // User or service on whose behalf the event has been recorded.
+    pub principal: Option<String>,
+    // Free-form headers, e.g. a trace id or the name of the client application.
+    pub headers: HashMap<String, String>,
+}
+
+impl EventMetadata {
+    /// Metadata for an event caused by the given event envelope.  The correlation id and principal
+    /// are carried over and the envelope becomes the cause.
+    pub fn caused_by<Event: EventType + Serialize>(event_envelope: &EventEnvelope<Event>) -> Self {
+        Self {
+            correlation_id: event_envelope
+                .metadata
+                .correlation_id
+                .or(Some(event_envelope.id)),
+            causation_id: Some(event_envelope.id),
+            principal: event_envelope.metadata.principal.clone(),
+            headers: HashMap::new(),
+        }
+    }
+
+    pub fn with_correlation_id(mut self, correlation_id: Uuid) -> Self {
+        self.correlation_id = Some(correlation_id);
+        self
+    }
+
+    pub fn with_causation_id(mut self, causation_id: Uuid) -> Self {
+        self.causation_id = Some(causation_id);
+        self
+    }
+
+    pub fn with_principal(mut self, principal: impl Into<String>) -> Self {
+        self.principal = Some(principal.into());
+        self
+    }
+
+    pub fn with_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// Settings attached to the stream of an aggregate.
+///
+/// Events falling outside of the max age or max count are left out when the stream is read, and
+/// removed by the next scavenge of the event store.  The latest event of a stream is always
+/// retained, even when it has expired, so that the stream keeps its version and aggregates loaded
+/// from it can still be written to.
+///
+/// # Example
+///
+/// ```
+/// # use std::time::Duration;
+/// # use chrono::Utc;
+/// # use event_sourcing::event::metadata::StreamMetadata;
+///
+/// let metadata = StreamMetadata::default()
+///     .with_max_age(Duration::from_secs(3600))
+///     .with_max_count(100)
+///     .with_acl("telemetry");
+///
+/// # let now = Utc::now();
+/// # assert!(metadata.retains(150, now, 200, now));
+/// # assert!(!metadata.retains(100, now, 200, now));
+/// # assert!(!metadata.retains(199, now - chrono::Duration::hours(2), 200, now));
+/// # assert!(metadata.retains(200, now - chrono::Duration::hours(2), 200, now));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamMetadata {
+    // Events recorded longer ago than this are expired.
+    pub max_age: Option<Duration>,
+    // Only this many of the latest events are retained.
+    pub max_count: Option<i64>,
+    // Access control tag of the stream, left for the application to enforce.
+    pub acl: Option<String>,
+    // Free-form settings of the application.
+    pub custom: Option<Value>,
+}
+
+impl StreamMetadata {
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_max_count(mut self, max_count: i64) -> Self {
+        self.max_count = Some(max_count);
+        self
+    }
+
+    pub fn with_acl(mut self, acl: impl Into<String>) -> Self {
+        self.acl = Some(acl.into());
+        self
+    }
+
+    pub fn with_custom(mut self, custom: Value) -> Self {
+        self.custom = Some(custom);
+        self
+    }
+
+    /// Whether the metadata expires any events.
+    pub fn has_retention(&self) -> bool {
+        self.max_age.is_some() || self.max_count.is_some()
+    }
+
+    /// Whether the event at `version`, recorded at `timestamp`, is retained at `now` while its
+    /// stream is at `current_version`.  The latest event is always retained.
+    pub fn retains(
+        &self,
+        version: i64,
+        timestamp: DateTime<Utc>,
+        current_version: i64,
+        now: DateTime<Utc>,
+    ) -> bool {
+        if version >= current_version {
+            return true;
+        }
+        self.max_count
+            .is_none_or(|max_count| version > current_version - max_count)
+            && self.max_age.is_none_or(|max_age| {
+                chrono::Duration::from_std(max_age)
+                    .ok()
+                    .and_then(|max_age| now.checked_sub_signed(max_age))
+                    .is_none_or(|expired_before| timestamp >= expired_before)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    struct TestEvent {
+        description: String,
+    }
+
+    impl EventType for TestEvent {
+        fn event_type(&self) -> String {
+            String::from("TestEvent")
+        }
+    }
+
+    #[test]
+    fn it_derives_metadata_from_the_cause() {
+        let correlation_id = Uuid::new_v4();
+        let event_envelope = EventEnvelope::new(
+            String::from("aggregate_id"),
+            String::from("TestAggregate"),
+            TestEvent {
+                description: String::from("Deposit"),
+            },
+            String::from("TestEvent"),
+            1,
+        )
+        .with_metadata(
+            EventMetadata::default()
+                .with_correlation_id(correlation_id)
+                .with_principal("user@example.com")
+                .with_header("client", "web"),
+        );
+        let metadata = EventMetadata::caused_by(&event_envelope);
+        assert_eq!(metadata.correlation_id, Some(correlation_id));
+        assert_eq!(metadata.causation_id, Some(event_envelope.id));
+        assert_eq!(metadata.principal, Some(String::from("user@example.com")));
+        assert!(metadata.headers.is_empty());
+    }
+
+    #[test]
+    fn it_deserializes_missing_fields_as_empty() {
+        let metadata: EventMetadata = serde_json::from_str("{\"principal\":\"user@example.com\"}")
+            .expect("expected metadata");
+        assert_eq!(
+            metadata,
+            EventMetadata::default().with_principal("user@example.com")
+        );
+    }
+}
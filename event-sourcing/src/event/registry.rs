@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::event::EventType;
+use crate::Error;
+
+type Deserializer<Event> = Arc<dyn Fn(Value) -> Result<Event, Error> + Send + Sync>;
+
+/// Registry mapping the `event_type` of an envelope to the deserializer of its data.
+///
+/// This lets an event enum be stored without a serde tag: each variant is serialized as its inner
+/// struct and decoded back from the `event_type` recorded next to it.  Return the registry from
+/// `EventType::registry` so the event stores and listeners use it.
+///
+/// # Example
+///
+/// ```
+/// # use std::sync::OnceLock;
+/// # use serde::{Deserialize, Serialize};
+/// # use serde_json::json;
+/// # use event_sourcing::event::EventType;
+/// # use event_sourcing::event::registry::EventRegistry;
+///
+/// #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// struct Deposited {
+///     amount: i64,
+/// }
+///
+/// #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// struct Withdrawn {
+///     amount: i64,
+/// }
+///
+/// #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// #[serde(untagged)]
+/// enum AccountEvent {
+///     Deposited(Deposited),
+///     Withdrawn(Withdrawn),
+/// }
+///
+/// impl EventType for AccountEvent {
+///     fn event_type(&self) -> String {
+///         match self {
+///             AccountEvent::Deposited(_) => String::from("Deposited"),
+///             AccountEvent::Withdrawn(_) => String::from("Withdrawn"),
+///         }
+///     }
+///
+///     fn registry() -> Option<&'static EventRegistry<Self>> {
+///         static REGISTRY: OnceLock<EventRegistry<AccountEvent>> = OnceLock::new();
+///         Some(REGISTRY.get_or_init(|| {
+///             EventRegistry::default()
+///                 .register("Deposited", AccountEvent::Deposited)
+///                 .register("Withdrawn", AccountEvent::Withdrawn)
+///         }))
+///     }
+/// }
+///
+/// let registry = AccountEvent::registry().expect("expected registry");
+/// let event = registry
+///     .deserialize("Withdrawn", json!({ "amount": 1 }))
+///     .expect("expected event");
+///
+/// # assert_eq!(event, Some(AccountEvent::Withdrawn(Withdrawn { amount: 1 })));
+/// # assert!(registry.deserialize("Closed", json!({})).is_err());
+/// ```
+pub struct EventRegistry<Event> {
+    deserializers: HashMap<String, Deserializer<Event>>,
+    skip_unknown: bool,
+}
+
+impl<Event: 'static> EventRegistry<Event> {
+    /// Register the type of the data stored for `event_type` and how it becomes an event.
+    pub fn register<Data: DeserializeOwned + 'static>(
+        mut self,
+        event_type: impl Into<String>,
+        into_event: fn(Data) -> Event,
+    ) -> Self {
+        self.deserializers.insert(
+            event_type.into(),
+            Arc::new(move |data| Ok(into_event(serde_json::from_value(data)?))),
+        );
+        self
+    }
+
+    /// Skip envelopes with an event type that has not been registered when reading a stream instead
+    /// of failing with `Error::UnknownEventType`.  Skipped envelopes still count towards the version
+    /// an aggregate is loaded at.
+    pub fn skip_unknown(mut self) -> Self {
+        self.skip_unknown = true;
+        self
+    }
+
+    /// Deserialize the data of an envelope with the given event type.  Returns `None` when the event
+    /// type has not been registered and unknown event types are skipped.
+    pub fn deserialize(&self, event_type: &str, data: Value) -> Result<Option<Event>, Error> {
+        match self.deserializers.get(event_type) {
+            Some(deserializer) => deserializer(data).map(Some),
+            None if self.skip_unknown => Ok(None),
+            None => Err(Error::UnknownEventType(String::from(event_type))),
+        }
+    }
+}
+
+impl<Event> Default for EventRegistry<Event> {
+    fn default() -> Self {
+        Self {
+            deserializers: HashMap::new(),
+            skip_unknown: false,
+        }
+    }
+}
+
+impl<Event> Clone for EventRegistry<Event> {
+    fn clone(&self) -> Self {
+        Self {
+            deserializers: self.deserializers.clone(),
+            skip_unknown: self.skip_unknown,
+        }
+    }
+}
+
+impl<Event> fmt::Debug for EventRegistry<Event> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventRegistry")
+            .field(
+                "event_types",
+                &self.deserializers.keys().collect::<Vec<_>>(),
+            )
+            .field("skip_unknown", &self.skip_unknown)
+            .finish()
+    }
+}
+
+/// Deserialize the data of an envelope with the registry of the event, or with its `Deserialize`
+/// implementation when it does not have one.  Returns `None` when the envelope should be skipped.
+pub fn deserialize_data<Event: EventType + DeserializeOwned>(
+    event_type: &str,
+    data: Value,
+) -> Result<Option<Event>, Error> {
+    match Event::registry() {
+        Some(registry) => registry.deserialize(event_type, data),
+        None => Ok(Some(serde_json::from_value(data)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    struct Deposited {
+        amount: i64,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    struct Renamed {
+        description: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum TestEvent {
+        Deposited(Deposited),
+        Renamed(Renamed),
+    }
+
+    fn registry() -> EventRegistry<TestEvent> {
+        EventRegistry::default()
+            .register("Deposited", TestEvent::Deposited)
+            .register("Renamed", TestEvent::Renamed)
+    }
+
+    #[test]
+    fn it_deserializes_by_event_type() {
+        let registry = registry();
+        assert_eq!(
+            registry
+                .deserialize("Deposited", json!({ "amount": 1 }))
+                .expect("expected event"),
+            Some(TestEvent::Deposited(Deposited { amount: 1 }))
+        );
+        assert_eq!(
+            registry
+                .deserialize("Renamed", json!({ "description": "Savings" }))
+                .expect("expected event"),
+            Some(TestEvent::Renamed(Renamed {
+                description: String::from("Savings")
+            }))
+        );
+        assert!(matches!(
+            registry.deserialize("Renamed", json!({ "amount": 1 })),
+            Err(Error::Serialization(_))
+        ));
+    }
+
+    #[test]
+    fn it_reports_or_skips_unknown_event_types() {
+        let error = registry()
+            .deserialize("Closed", json!({}))
+            .expect_err("expected unknown event type");
+        assert!(matches!(error, Error::UnknownEventType(event_type) if event_type == "Closed"));
+        assert_eq!(
+            registry()
+                .skip_unknown()
+                .deserialize("Closed", json!({}))
+                .expect("expected skipped event"),
+            None
+        );
+    }
+}
@@ -1,10 +1,20 @@
+pub mod in_memory;
+pub mod scavenger;
+pub mod subscription;
+
+use std::future::Future;
+
 use crate::Error;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::event::envelope::EventEnvelope;
+use crate::event::metadata::StreamMetadata;
+use crate::event::store::subscription::CatchUpSubscription;
 use crate::event::EventType;
 
+#[allow(clippy::ptr_arg)]
 #[async_trait::async_trait]
 pub trait EventStore: Sized + Send + Sync + Clone {
     // Fetch all events for the aggregate.
@@ -18,9 +28,353 @@ pub trait EventStore: Sized + Send + Sync + Clone {
         aggregate_id: &String,
         version: i64,
     ) -> Result<Vec<EventEnvelope<Event>>, Error>;
-    // Persist the event for the aggregate.
+    // Fetch the events between two versions of the aggregate, both included.
+    async fn read_range<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        from_version: i64,
+        to_version: i64,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error>;
+    // Fetch all events up to and including the specified version for the aggregate.
+    async fn read_to_version<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        version: i64,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        self.read_range(aggregate_id, i64::MIN, version).await
+    }
+    // Fetch up to `limit` events on and before the specified version for the aggregate, latest
+    // first.
+    async fn read_backward<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        version: i64,
+        limit: usize,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error>;
+    // Fetch the latest `count` events for the aggregate, oldest first.
+    async fn read_latest<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        count: usize,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        let mut event_envelopes = self.read_backward(aggregate_id, i64::MAX, count).await?;
+        event_envelopes.reverse();
+        Ok(event_envelopes)
+    }
+    // Stream all events for the aggregate.
+    fn stream<'a, Event: EventType + Serialize + DeserializeOwned>(
+        &'a self,
+        aggregate_id: &'a String,
+    ) -> BoxStream<'a, Result<EventEnvelope<Event>, Error>> {
+        self.stream_from(aggregate_id, i64::MIN)
+    }
+    // Stream all events on and after the specified version for the aggregate, fetching them from
+    // the backend a page at a time instead of buffering the whole stream.
+    fn stream_from<'a, Event: EventType + Serialize + DeserializeOwned>(
+        &'a self,
+        aggregate_id: &'a String,
+        version: i64,
+    ) -> BoxStream<'a, Result<EventEnvelope<Event>, Error>>;
+    // Persist the event for the aggregate, failing with `Error::VersionConflict` when the aggregate
+    // is not at the expected version or the envelope's version has already been written, and with
+    // `Error::InvalidArgument` when the envelope is not at the version after the current one.
     async fn persist<Event: EventType + Serialize + DeserializeOwned>(
         &self,
         event_envelope: EventEnvelope<Event>,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error>;
+    // Persist all events for the aggregate atomically at consecutive versions after the current
+    // version, and return the version of the aggregate after the last event.  Envelopes at version
+    // `0` are given the version they are stored at, and the batch fails with
+    // `Error::InvalidArgument` when any other envelope is not at it.
+    async fn append<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        event_envelopes: Vec<EventEnvelope<Event>>,
+        expected_version: ExpectedVersion,
+    ) -> Result<i64, Error>;
+    // Fetch the version of the aggregate, which is the version of its latest stored event whether
+    // or not it is skipped when read, or `0` when it does not have any events.
+    async fn read_version(&self, aggregate_id: &String) -> Result<i64, Error>;
+    // Fetch up to `limit` events of every aggregate that match the filter, on and after the global
+    // position, in the order they were stored, along with the position the next read resumes from.
+    // Stores that bound how much of the log one read scans may return fewer events, or none, with a
+    // next position past the scanned events.  The log has been read up to its end once the next
+    // position no longer moves.
+    async fn read_all<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        from_position: i64,
+        limit: usize,
+        filter: &EventFilter,
+    ) -> Result<EventBatch<Event>, Error>;
+    // Tombstone the stream of the aggregate.  Its events are kept, but the stream reads as empty,
+    // its events are left out of `read_all` and writing to it fails with `Error::StreamDeleted`.
+    async fn soft_delete(
+        &self,
+        aggregate_id: &String,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error>;
+    // Tombstone the stream of the aggregate and erase its events, e.g. to honour a request for
+    // erasure.  Erasing a stream that has already been deleted succeeds.  Snapshots of the aggregate
+    // are kept by their own store, which `AggregateRepository::hard_delete` erases as well.
+    async fn hard_delete(
+        &self,
+        aggregate_id: &String,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error>;
+    // Erase the events of the aggregate before the version, e.g. once a snapshot covers them.  The
+    // latest event is always kept so that the stream keeps its version.  Fails with
+    // `Error::StreamDeleted` when the stream has been deleted.
+    async fn truncate_before(&self, aggregate_id: &String, version: i64) -> Result<(), Error>;
+    // Fetch the settings of the stream of the aggregate, or the default settings when none have
+    // been written.
+    async fn read_stream_metadata(&self, aggregate_id: &String) -> Result<StreamMetadata, Error>;
+    // Replace the settings of the stream of the aggregate.  Reads apply its retention right away.
+    async fn write_stream_metadata(
+        &self,
+        aggregate_id: &String,
+        metadata: StreamMetadata,
     ) -> Result<(), Error>;
+    // Erase the events that have expired according to the metadata of their stream and return how
+    // many were erased.  The latest event of every stream is kept so that it keeps its version.
+    async fn scavenge(&self) -> Result<usize, Error>;
+    // Stream of wake-ups sent whenever new events have been stored, or `None` when the store cannot
+    // notify subscribers and they have to poll it.
+    fn notifications(&self) -> Option<BoxStream<'static, ()>> {
+        None
+    }
+    // Subscribe to the events of every aggregate that match the filter, replaying them from the
+    // global position on before delivering new ones as they are stored.
+    fn subscribe<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        from_position: i64,
+        filter: EventFilter,
+    ) -> BoxStream<'static, Result<EventEnvelope<Event>, Error>>
+    where
+        Self: 'static,
+    {
+        CatchUpSubscription::new(self.clone(), from_position)
+            .with_filter(filter)
+            .stream()
+    }
+}
+
+/// Stream the envelopes of an aggregate page by page, starting at `version`.
+///
+/// `fetch_page` returns the envelopes on and after a version, along with the version the next page
+/// starts at, or `None` once the last page has been fetched.  Pages are only fetched when the stream
+/// is polled past the envelopes of the previous page.
+///
+/// # Example
+///
+/// ```
+/// # use futures::TryStreamExt;
+/// # use serde::{Deserialize, Serialize};
+/// # use event_sourcing::event::envelope::EventEnvelope;
+/// # use event_sourcing::event::store::paged_stream;
+/// # use event_sourcing::event::EventType;
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// # }
+///
+/// # futures::executor::block_on(async {
+/// let event_envelopes: Vec<EventEnvelope<TestEvent>> = paged_stream(1, |version| async move {
+///     let page = (version..(version + 2).min(6))
+///         .map(|version| {
+///             EventEnvelope::new(
+///                 String::from("aggregate_id"),
+///                 String::from("TestAggregate"),
+///                 TestEvent { description: String::from("Deposit") },
+///                 String::from("TestEvent"),
+///                 version,
+///             )
+///         })
+///         .collect::<Vec<_>>();
+///     let next_version = (version + 2 < 6).then_some(version + 2);
+///     Ok((page, next_version))
+/// })
+/// .try_collect()
+/// .await
+/// .expect("expected events");
+///
+/// # assert_eq!(event_envelopes.iter().map(|event_envelope| event_envelope.version).collect::<Vec<i64>>(), vec![1, 2, 3, 4, 5]);
+/// # });
+/// ```
+pub fn paged_stream<'a, Event, FetchPage, Page>(
+    version: i64,
+    fetch_page: FetchPage,
+) -> BoxStream<'a, Result<EventEnvelope<Event>, Error>>
+where
+    Event: EventType + Serialize,
+    FetchPage: FnMut(i64) -> Page + Send + 'a,
+    Page: Future<Output = Result<(Vec<EventEnvelope<Event>>, Option<i64>), Error>> + Send + 'a,
+{
+    stream::try_unfold(
+        (Some(version), fetch_page),
+        |(version, mut fetch_page)| async move {
+            let Some(version) = version else {
+                return Ok(None);
+            };
+            let (event_envelopes, next_version) = fetch_page(version).await?;
+            Ok::<_, Error>(Some((
+                stream::iter(event_envelopes.into_iter().map(Ok)),
+                (next_version, fetch_page),
+            )))
+        },
+    )
+    .try_flatten()
+    .boxed()
+}
+
+/// Fail with `Error::InvalidArgument` unless the envelopes follow on from the current version of the
+/// aggregate without gaps, the first at `current_version + 1` and every next one a version later.
+/// Envelopes at version `0` have not been given a version yet and are accepted at any version.
+pub fn check_versions<Event: EventType + Serialize>(
+    aggregate_id: &str,
+    current_version: i64,
+    event_envelopes: &[EventEnvelope<Event>],
+) -> Result<(), Error> {
+    match event_envelopes
+        .iter()
+        .zip(current_version + 1..)
+        .find(|(event_envelope, version)| {
+            event_envelope.version != 0 && event_envelope.version != *version
+        }) {
+        Some((event_envelope, version)) => Err(Error::InvalidArgument(format!(
+            "event envelope at version {} cannot be stored at version {} of aggregate `{}`",
+            event_envelope.version, version, aggregate_id
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Filter applied to the events returned by `EventStore::read_all`.  The default filter keeps every
+/// event.
+///
+/// # Example
+///
+/// ```
+/// # use event_sourcing::event::store::EventFilter;
+///
+/// let filter = EventFilter::default().with_aggregate_type("TestAggregate");
+///
+/// # assert!(filter.matches("TestAggregate", "TestEvent"));
+/// # assert!(!filter.matches("OtherAggregate", "TestEvent"));
+/// # assert!(!filter.clone().with_event_type("OtherEvent").matches("TestAggregate", "TestEvent"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventFilter {
+    // Only keep events of aggregates of this type.
+    pub aggregate_type: Option<String>,
+    // Only keep events of this type.
+    pub event_type: Option<String>,
+}
+
+impl EventFilter {
+    /// Only keep events of aggregates of the given type.
+    pub fn with_aggregate_type(mut self, aggregate_type: impl Into<String>) -> Self {
+        self.aggregate_type = Some(aggregate_type.into());
+        self
+    }
+
+    /// Only keep events of the given type.
+    pub fn with_event_type(mut self, event_type: impl Into<String>) -> Self {
+        self.event_type = Some(event_type.into());
+        self
+    }
+
+    /// Check whether an event of `event_type` belonging to an aggregate of `aggregate_type` is kept.
+    pub fn matches(&self, aggregate_type: &str, event_type: &str) -> bool {
+        self.aggregate_type
+            .as_deref()
+            .is_none_or(|expected| expected == aggregate_type)
+            && self
+                .event_type
+                .as_deref()
+                .is_none_or(|expected| expected == event_type)
+    }
+}
+
+/// Events returned by `EventStore::read_all`.
+///
+/// The next position is past every event that has been scanned, whether or not it matched the
+/// filter, so that reads with a selective filter do not scan the same events again.
+#[derive(Debug, Clone)]
+pub struct EventBatch<Event: EventType + Serialize> {
+    // Events matching the filter, in the order they were stored.
+    pub event_envelopes: Vec<EventEnvelope<Event>>,
+    // Global position the next read resumes from.
+    pub next_position: i64,
+}
+
+/// Version the aggregate is expected to be at before new events are persisted.
+///
+/// The current version of an aggregate is the version of its latest event, or `0` when it has no
+/// events yet, so `ExpectedVersion::Exact(0)` is equivalent to `ExpectedVersion::NoStream`.
+///
+/// # Example
+///
+/// ```
+/// # use event_sourcing::event::store::ExpectedVersion;
+///
+/// assert!(ExpectedVersion::Any.is_satisfied_by(3));
+/// assert!(ExpectedVersion::NoStream.is_satisfied_by(0));
+/// assert!(ExpectedVersion::Exact(3).is_satisfied_by(3));
+/// assert!(!ExpectedVersion::Exact(2).is_satisfied_by(3));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExpectedVersion {
+    // Persist regardless of the current version of the aggregate.
+    Any,
+    // Persist only if the aggregate does not have any events yet.
+    NoStream,
+    // Persist only if the aggregate is currently at this version.
+    Exact(i64),
+}
+
+impl ExpectedVersion {
+    /// Check whether an aggregate at `current_version` meets the expectation.
+    pub fn is_satisfied_by(&self, current_version: i64) -> bool {
+        match self {
+            ExpectedVersion::Any => true,
+            ExpectedVersion::NoStream => current_version == 0,
+            ExpectedVersion::Exact(version) => current_version == *version,
+        }
+    }
+}
+
+/// Error returned when an aggregate has been modified concurrently.
+///
+/// The caller should reload the aggregate and retry the command.  It is surfaced as
+/// `Error::VersionConflict`.
+///
+/// # Example
+///
+/// ```
+/// # use event_sourcing::Error;
+/// # use event_sourcing::event::store::{ExpectedVersion, VersionConflictError};
+///
+/// let error: Error = VersionConflictError::new(String::from("aggregate_id"), ExpectedVersion::NoStream, 1).into();
+///
+/// # assert!(matches!(error, Error::VersionConflict(VersionConflictError { actual_version: 1, .. })));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, derive_new::new)]
+#[error(
+    "version conflict for aggregate `{aggregate_id}`: expected {expected_version:?}, actual version {actual_version}"
+)]
+pub struct VersionConflictError {
+    // ID of the aggregate that was modified concurrently.
+    pub aggregate_id: String,
+    // Version the caller expected the aggregate to be at.
+    pub expected_version: ExpectedVersion,
+    // Version the aggregate was actually at.
+    pub actual_version: i64,
 }
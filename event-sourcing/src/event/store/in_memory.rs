@@ -0,0 +1,1605 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::codec::{Codec, ContentType, JsonCodec};
+use crate::event::envelope::{decode, encode, EventEnvelope};
+use crate::event::metadata::StreamMetadata;
+use crate::event::store::subscription::Notifier;
+use crate::event::store::{
+    check_versions, paged_stream, EventBatch, EventFilter, EventStore, ExpectedVersion,
+    VersionConflictError,
+};
+use crate::event::upcaster::UpcasterRegistry;
+use crate::event::EventType;
+use crate::Error;
+
+/// Event store that keeps every envelope in memory, intended for tests and local development.
+///
+/// Clones share the same underlying state, so a single store can be handed to many tasks.  Every
+/// stored event wakes up the subscriptions of the store, which never have to poll it.
+///
+/// # Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use uuid::Uuid;
+/// # use serde::{Deserialize, Serialize};
+/// # use event_sourcing::event::envelope::EventEnvelope;
+/// # use event_sourcing::event::store::{EventStore, ExpectedVersion};
+/// # use event_sourcing::event::store::in_memory::InMemoryEventStore;
+/// # use event_sourcing::event::EventType;
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     id: Uuid,
+/// #     amount: i64,
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// # }
+///
+/// # futures::executor::block_on(async {
+/// # let test_event = TestEvent {
+/// #     id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
+/// #     amount: 1,
+/// #     description: String::from("Deposit"),
+/// # };
+/// let event_store = InMemoryEventStore::default();
+/// let event_envelope = EventEnvelope::new(
+///     String::from("aggregate_id"),
+///     String::from("TestAggregate"),
+///     test_event.clone(),
+///     test_event.event_type(),
+///     1,
+/// );
+/// event_store
+///     .persist(event_envelope, ExpectedVersion::NoStream)
+///     .await
+///     .expect("expected persisted event");
+/// let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+///     .read(&String::from("aggregate_id"))
+///     .await
+///     .expect("expected events");
+///
+/// # assert_eq!(event_envelopes.len(), 1);
+/// # assert_eq!(event_envelopes[0].data, test_event);
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct InMemoryEventStore<C = JsonCodec> {
+    streams: Arc<RwLock<Streams>>,
+    // Global position of the latest stored event, only advanced while the streams are write locked.
+    position: Arc<AtomicI64>,
+    upcasters: UpcasterRegistry,
+    codec: C,
+    // Number of events decoded at a time when a stream is read as a `Stream`.
+    page_size: usize,
+    notifier: Notifier,
+}
+
+impl Default for InMemoryEventStore {
+    fn default() -> Self {
+        Self {
+            streams: Arc::default(),
+            position: Arc::default(),
+            upcasters: UpcasterRegistry::default(),
+            codec: JsonCodec,
+            page_size: 500,
+            notifier: Notifier::default(),
+        }
+    }
+}
+
+// Events of every aggregate, along with the settings of their streams and the aggregates whose
+// stream has been deleted.
+#[derive(Debug, Default)]
+struct Streams {
+    events: HashMap<String, Vec<StoredEvent>>,
+    metadata: HashMap<String, StreamMetadata>,
+    // Aggregates whose stream has been deleted.  Soft deleted streams keep their events.
+    tombstones: HashSet<String>,
+    // Aggregate and version of the event stored at every global position, so that events can be
+    // read in the order they were stored.
+    positions: BTreeMap<i64, (String, i64)>,
+}
+
+impl Streams {
+    // Events of the aggregate, or `None` when its stream does not exist or has been deleted.
+    fn get(&self, aggregate_id: &str) -> Option<&Vec<StoredEvent>> {
+        if self.tombstones.contains(aggregate_id) {
+            return None;
+        }
+        self.events.get(aggregate_id)
+    }
+
+    // Events of the aggregate to be written to, failing when its stream has been deleted.
+    fn get_mut(&mut self, aggregate_id: &str) -> Result<&mut Vec<StoredEvent>, Error> {
+        if self.tombstones.contains(aggregate_id) {
+            return Err(Error::StreamDeleted(String::from(aggregate_id)));
+        }
+        Ok(self.events.entry(String::from(aggregate_id)).or_default())
+    }
+
+    // Event of the aggregate at the version, or `None` when it has been erased or its stream has
+    // been deleted.
+    fn find(&self, aggregate_id: &str, version: i64) -> Option<&StoredEvent> {
+        let stream = self.get(aggregate_id)?;
+        let index = stream.partition_point(|stored_event| stored_event.version < version);
+        stream
+            .get(index)
+            .filter(|stored_event| stored_event.version == version)
+    }
+
+    // Whether an event of the aggregate is still retained by the metadata of its stream.
+    fn retention(&self, aggregate_id: &str) -> impl Fn(&StoredEvent) -> bool + '_ {
+        let metadata = self.metadata.get(aggregate_id);
+        let current_version = self
+            .events
+            .get(aggregate_id)
+            .and_then(|stream| stream.last())
+            .map_or(0, |stored_event| stored_event.version);
+        let now = Utc::now();
+        move |stored_event| {
+            metadata.is_none_or(|metadata| {
+                metadata.retains(
+                    stored_event.version,
+                    stored_event.timestamp,
+                    current_version,
+                    now,
+                )
+            })
+        }
+    }
+
+    // Tombstone the stream of the aggregate when it is at the expected version.
+    fn tombstone(
+        &mut self,
+        aggregate_id: &str,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        let current_version = self
+            .events
+            .get(aggregate_id)
+            .and_then(|stream| stream.last())
+            .map_or(0, |stored_event| stored_event.version);
+        if !expected_version.is_satisfied_by(current_version) {
+            return Err(VersionConflictError::new(
+                String::from(aggregate_id),
+                expected_version,
+                current_version,
+            )
+            .into());
+        }
+        self.tombstones.insert(String::from(aggregate_id));
+        Ok(())
+    }
+}
+
+// Encoded envelope kept alongside its version and position so events can be ordered and filtered
+// without knowing the event type, and alongside its content type so it can be decoded whatever codec
+// wrote it.
+#[derive(Debug, Clone)]
+struct StoredEvent {
+    version: i64,
+    position: i64,
+    timestamp: DateTime<Utc>,
+    aggregate_type: String,
+    event_type: String,
+    content_type: String,
+    envelope: Vec<u8>,
+}
+
+impl<C: Codec> InMemoryEventStore<C> {
+    /// Upcast events to their current shape when they are read.
+    pub fn with_upcasters(mut self, upcasters: UpcasterRegistry) -> Self {
+        self.upcasters = upcasters;
+        self
+    }
+
+    /// Encode new events with the codec.  Events that have already been stored keep the content
+    /// type they were written with.
+    pub fn with_codec<D: Codec>(self, codec: D) -> InMemoryEventStore<D> {
+        InMemoryEventStore {
+            streams: self.streams,
+            position: self.position,
+            upcasters: self.upcasters,
+            codec,
+            page_size: self.page_size,
+            notifier: self.notifier,
+        }
+    }
+
+    /// Decode streamed events `page_size` at a time.
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    fn read_stream<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &str,
+        filter: impl Fn(&StoredEvent) -> bool,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        let streams = self
+            .streams
+            .read()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        let retains = streams.retention(aggregate_id);
+        streams
+            .get(aggregate_id)
+            .map(|stream| {
+                stream
+                    .iter()
+                    .filter(|stored_event| filter(stored_event) && retains(stored_event))
+                    .filter_map(|stored_event| self.decode(stored_event).transpose())
+                    .collect()
+            })
+            .unwrap_or_else(|| Ok(Vec::new()))
+    }
+
+    // Decode up to `page_size` events on and after the version, along with the version of the next
+    // page when there may be one.
+    fn read_page<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &str,
+        version: i64,
+    ) -> Result<(Vec<EventEnvelope<Event>>, Option<i64>), Error> {
+        let streams = self
+            .streams
+            .read()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        let Some(stream) = streams.get(aggregate_id) else {
+            return Ok((Vec::new(), None));
+        };
+        let index = stream.partition_point(|stored_event| stored_event.version < version);
+        let page = &stream[index..stream.len().min(index + self.page_size)];
+        let next_version = match page.last() {
+            Some(stored_event) if index + page.len() < stream.len() => {
+                Some(stored_event.version + 1)
+            }
+            _ => None,
+        };
+        let retains = streams.retention(aggregate_id);
+        let event_envelopes = page
+            .iter()
+            .filter(|stored_event| retains(stored_event))
+            .filter_map(|stored_event| self.decode(stored_event).transpose())
+            .collect::<Result<Vec<EventEnvelope<Event>>, Error>>()?;
+        Ok((event_envelopes, next_version))
+    }
+
+    // Decode the event with the codec of the store when it wrote it, and with the built-in codec of
+    // its content type otherwise.
+    fn decode<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        stored_event: &StoredEvent,
+    ) -> Result<Option<EventEnvelope<Event>>, Error> {
+        if stored_event.content_type == self.codec.content_type() {
+            return decode(&self.codec, &stored_event.envelope, &self.upcasters);
+        }
+        decode(
+            &stored_event.content_type.parse::<ContentType>()?,
+            &stored_event.envelope,
+            &self.upcasters,
+        )
+    }
+
+    // Encode the envelope at the given global position.  Callers advance `position` once the
+    // envelope has been stored, so a failed write never leaves a gap.
+    fn encode<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        mut event_envelope: EventEnvelope<Event>,
+        position: i64,
+    ) -> Result<StoredEvent, Error> {
+        event_envelope.position = position;
+        Ok(StoredEvent {
+            version: event_envelope.version,
+            position,
+            timestamp: event_envelope.timestamp,
+            aggregate_type: event_envelope.aggregate_type.clone(),
+            event_type: event_envelope.event_type.clone(),
+            content_type: String::from(self.codec.content_type()),
+            envelope: encode(&event_envelope, &self.codec)?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: Codec + 'static> EventStore for InMemoryEventStore<C> {
+    async fn read<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        self.read_stream(aggregate_id, |_| true)
+    }
+
+    async fn read_from<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        version: i64,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        self.read_stream(aggregate_id, |stored_event| stored_event.version >= version)
+    }
+
+    async fn read_range<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        from_version: i64,
+        to_version: i64,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        self.read_stream(aggregate_id, |stored_event| {
+            (from_version..=to_version).contains(&stored_event.version)
+        })
+    }
+
+    async fn read_backward<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        version: i64,
+        limit: usize,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        let streams = self
+            .streams
+            .read()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        let retains = streams.retention(aggregate_id);
+        streams
+            .get(aggregate_id)
+            .map(|stream| {
+                stream
+                    .iter()
+                    .rev()
+                    .filter(|stored_event| stored_event.version <= version && retains(stored_event))
+                    .filter_map(|stored_event| self.decode(stored_event).transpose())
+                    .take(limit)
+                    .collect()
+            })
+            .unwrap_or_else(|| Ok(Vec::new()))
+    }
+
+    fn stream_from<'a, Event: EventType + Serialize + DeserializeOwned>(
+        &'a self,
+        aggregate_id: &'a String,
+        version: i64,
+    ) -> BoxStream<'a, Result<EventEnvelope<Event>, Error>> {
+        paged_stream(version, move |version| async move {
+            self.read_page(aggregate_id, version)
+        })
+    }
+
+    async fn persist<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        event_envelope: EventEnvelope<Event>,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        let mut streams = self
+            .streams
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        let stream = streams.get_mut(&event_envelope.aggregate_id)?;
+        let current_version = stream.last().map_or(0, |stored_event| stored_event.version);
+        let version_exists = (1..=current_version).contains(&event_envelope.version);
+        if version_exists || !expected_version.is_satisfied_by(current_version) {
+            return Err(VersionConflictError::new(
+                event_envelope.aggregate_id,
+                expected_version,
+                current_version,
+            )
+            .into());
+        }
+        if event_envelope.version != current_version + 1 {
+            return Err(Error::InvalidArgument(format!(
+                "event envelope at version {} cannot be stored at version {} of aggregate `{}`",
+                event_envelope.version,
+                current_version + 1,
+                event_envelope.aggregate_id
+            )));
+        }
+        let position = self.position.load(Ordering::SeqCst) + 1;
+        let aggregate_id = event_envelope.aggregate_id.clone();
+        let version = event_envelope.version;
+        stream.push(self.encode(event_envelope, position)?);
+        streams.positions.insert(position, (aggregate_id, version));
+        self.position.store(position, Ordering::SeqCst);
+        self.notifier.notify();
+        Ok(())
+    }
+
+    async fn append<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        event_envelopes: Vec<EventEnvelope<Event>>,
+        expected_version: ExpectedVersion,
+    ) -> Result<i64, Error> {
+        if let Some(event_envelope) = event_envelopes
+            .iter()
+            .find(|event_envelope| event_envelope.aggregate_id != *aggregate_id)
+        {
+            return Err(Error::InvalidArgument(format!(
+                "event envelope for aggregate `{}` cannot be appended to aggregate `{}`",
+                event_envelope.aggregate_id, aggregate_id
+            )));
+        }
+        let mut streams = self
+            .streams
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        let stream = streams.get_mut(aggregate_id)?;
+        let current_version = stream.last().map_or(0, |stored_event| stored_event.version);
+        if !expected_version.is_satisfied_by(current_version) {
+            return Err(VersionConflictError::new(
+                String::from(aggregate_id),
+                expected_version,
+                current_version,
+            )
+            .into());
+        }
+        check_versions(aggregate_id, current_version, &event_envelopes)?;
+        let position = self.position.load(Ordering::SeqCst);
+        let stored_events = event_envelopes
+            .into_iter()
+            .zip(current_version + 1..)
+            .zip(position + 1..)
+            .map(|((mut event_envelope, version), position)| {
+                event_envelope.version = version;
+                self.encode(event_envelope, position)
+            })
+            .collect::<Result<Vec<StoredEvent>, Error>>()?;
+        let (version, position) = stored_events
+            .last()
+            .map_or((current_version, position), |stored_event| {
+                (stored_event.version, stored_event.position)
+            });
+        let stored_positions: Vec<(i64, i64)> = stored_events
+            .iter()
+            .map(|stored_event| (stored_event.position, stored_event.version))
+            .collect();
+        stream.extend(stored_events);
+        streams.positions.extend(
+            stored_positions
+                .into_iter()
+                .map(|(position, version)| (position, (aggregate_id.clone(), version))),
+        );
+        self.position.store(position, Ordering::SeqCst);
+        self.notifier.notify();
+        Ok(version)
+    }
+
+    async fn read_version(&self, aggregate_id: &String) -> Result<i64, Error> {
+        let streams = self
+            .streams
+            .read()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        Ok(streams
+            .get(aggregate_id)
+            .and_then(|stream| stream.last())
+            .map_or(0, |stored_event| stored_event.version))
+    }
+
+    async fn read_all<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        from_position: i64,
+        limit: usize,
+        filter: &EventFilter,
+    ) -> Result<EventBatch<Event>, Error> {
+        let streams = self
+            .streams
+            .read()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        let mut retentions = HashMap::new();
+        let mut event_envelopes = Vec::new();
+        // Every stored event is scanned unless the limit is reached first.
+        let mut next_position = from_position.max(self.position.load(Ordering::SeqCst) + 1);
+        for (position, (aggregate_id, version)) in streams.positions.range(from_position..) {
+            if event_envelopes.len() == limit {
+                next_position = *position;
+                break;
+            }
+            let Some(stored_event) = streams.find(aggregate_id, *version) else {
+                continue;
+            };
+            let retains = retentions
+                .entry(aggregate_id.as_str())
+                .or_insert_with(|| streams.retention(aggregate_id));
+            if !filter.matches(&stored_event.aggregate_type, &stored_event.event_type)
+                || !retains(stored_event)
+            {
+                continue;
+            }
+            if let Some(event_envelope) = self.decode(stored_event)? {
+                event_envelopes.push(event_envelope);
+            }
+        }
+        Ok(EventBatch {
+            event_envelopes,
+            next_position,
+        })
+    }
+
+    async fn soft_delete(
+        &self,
+        aggregate_id: &String,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        let mut streams = self
+            .streams
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        if streams.tombstones.contains(aggregate_id) {
+            return Err(Error::StreamDeleted(String::from(aggregate_id)));
+        }
+        streams.tombstone(aggregate_id, expected_version)
+    }
+
+    async fn hard_delete(
+        &self,
+        aggregate_id: &String,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        let mut streams = self
+            .streams
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        if !streams.tombstones.contains(aggregate_id) {
+            streams.tombstone(aggregate_id, expected_version)?;
+        }
+        for stored_event in streams.events.remove(aggregate_id).unwrap_or_default() {
+            streams.positions.remove(&stored_event.position);
+        }
+        streams.metadata.remove(aggregate_id);
+        Ok(())
+    }
+
+    async fn truncate_before(&self, aggregate_id: &String, version: i64) -> Result<(), Error> {
+        let mut streams = self
+            .streams
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        if streams.tombstones.contains(aggregate_id) {
+            return Err(Error::StreamDeleted(String::from(aggregate_id)));
+        }
+        let streams = &mut *streams;
+        if let Some(stream) = streams.events.get_mut(aggregate_id) {
+            let index = stream
+                .partition_point(|stored_event| stored_event.version < version)
+                .min(stream.len().saturating_sub(1));
+            for stored_event in stream.drain(..index) {
+                streams.positions.remove(&stored_event.position);
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_stream_metadata(&self, aggregate_id: &String) -> Result<StreamMetadata, Error> {
+        let streams = self
+            .streams
+            .read()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        Ok(streams
+            .metadata
+            .get(aggregate_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn write_stream_metadata(
+        &self,
+        aggregate_id: &String,
+        metadata: StreamMetadata,
+    ) -> Result<(), Error> {
+        let mut streams = self
+            .streams
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        if streams.tombstones.contains(aggregate_id) {
+            return Err(Error::StreamDeleted(String::from(aggregate_id)));
+        }
+        streams
+            .metadata
+            .insert(String::from(aggregate_id), metadata);
+        Ok(())
+    }
+
+    async fn scavenge(&self) -> Result<usize, Error> {
+        let mut streams = self
+            .streams
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        let Streams {
+            events,
+            metadata,
+            tombstones,
+            positions,
+        } = &mut *streams;
+        let now = Utc::now();
+        let mut scavenged = 0;
+        for (aggregate_id, metadata) in metadata.iter() {
+            if tombstones.contains(aggregate_id) || !metadata.has_retention() {
+                continue;
+            }
+            let Some(stream) = events.get_mut(aggregate_id) else {
+                continue;
+            };
+            let Some(current_version) = stream.last().map(|stored_event| stored_event.version)
+            else {
+                continue;
+            };
+            let len = stream.len();
+            stream.retain(|stored_event| {
+                let retained = metadata.retains(
+                    stored_event.version,
+                    stored_event.timestamp,
+                    current_version,
+                    now,
+                );
+                if !retained {
+                    positions.remove(&stored_event.position);
+                }
+                retained
+            });
+            scavenged += len - stream.len();
+        }
+        Ok(scavenged)
+    }
+
+    fn notifications(&self) -> Option<BoxStream<'static, ()>> {
+        Some(self.notifier.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::OnceLock;
+    use std::time::Duration;
+
+    use futures::TryStreamExt;
+    use serde::Deserialize;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::event::registry::EventRegistry;
+    use crate::event::store::scavenger::Scavenger;
+    use crate::fixtures::{event_envelope, TestEvent};
+
+    #[tokio::test]
+    async fn it_rejects_events_out_of_version_order() {
+        let event_store = InMemoryEventStore::default();
+        let error = event_store
+            .persist(event_envelope("aggregate_id", 2, 2), ExpectedVersion::Any)
+            .await
+            .expect_err("expected gap to be rejected");
+        assert!(matches!(error, Error::InvalidArgument(_)));
+        event_store
+            .persist(event_envelope("aggregate_id", 1, 1), ExpectedVersion::Any)
+            .await
+            .expect("expected persisted event");
+        let error = event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![
+                    event_envelope("aggregate_id", 2, 2),
+                    event_envelope("aggregate_id", 4, 4),
+                ],
+                ExpectedVersion::Exact(1),
+            )
+            .await
+            .expect_err("expected gap to be rejected");
+        assert!(matches!(error, Error::InvalidArgument(_)));
+        let error = event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![
+                    event_envelope("aggregate_id", 3, 3),
+                    event_envelope("aggregate_id", 2, 2),
+                ],
+                ExpectedVersion::Any,
+            )
+            .await
+            .expect_err("expected out of order versions to be rejected");
+        assert!(matches!(error, Error::InvalidArgument(_)));
+        let version = event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![
+                    event_envelope("aggregate_id", 2, 2),
+                    event_envelope("aggregate_id", 3, 0),
+                ],
+                ExpectedVersion::Exact(1),
+            )
+            .await
+            .expect("expected appended events");
+        assert_eq!(version, 3);
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert_eq!(
+            event_envelopes
+                .iter()
+                .map(|event_envelope| (event_envelope.version, event_envelope.data.amount))
+                .collect::<Vec<(i64, i64)>>(),
+            vec![(1, 1), (2, 2), (3, 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reads_events_from_version() {
+        let event_store = InMemoryEventStore::default();
+        for version in 1..=3 {
+            event_store
+                .persist(
+                    event_envelope("aggregate_id", 1, version),
+                    ExpectedVersion::Exact(version - 1),
+                )
+                .await
+                .expect("expected persisted event");
+        }
+        event_store
+            .persist(
+                event_envelope("other_aggregate_id", 1, 1),
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected persisted event");
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read_from(&String::from("aggregate_id"), 2)
+            .await
+            .expect("expected events");
+        assert_eq!(
+            event_envelopes
+                .iter()
+                .map(|event_envelope| event_envelope.version)
+                .collect::<Vec<i64>>(),
+            vec![2, 3]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reads_nothing_for_unknown_aggregate() {
+        let event_store = InMemoryEventStore::default();
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert!(event_envelopes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_shares_state_between_concurrent_tasks() {
+        let event_store = InMemoryEventStore::default();
+        let handles: Vec<_> = (0..10)
+            .map(|task| {
+                let event_store = event_store.clone();
+                tokio::spawn(async move {
+                    for version in 1..=10 {
+                        event_store
+                            .persist(
+                                event_envelope(&format!("aggregate_{}", task), 1, version),
+                                ExpectedVersion::Exact(version - 1),
+                            )
+                            .await
+                            .expect("expected persisted event");
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.expect("expected task to complete");
+        }
+        for task in 0..10 {
+            let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+                .read(&format!("aggregate_{}", task))
+                .await
+                .expect("expected events");
+            assert_eq!(event_envelopes.len(), 10);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_rejects_unexpected_version() {
+        let event_store = InMemoryEventStore::default();
+        event_store
+            .persist(
+                event_envelope("aggregate_id", 1, 1),
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected persisted event");
+        let error = event_store
+            .persist(
+                event_envelope("aggregate_id", 1, 2),
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect_err("expected version conflict");
+        assert!(matches!(
+            error,
+            Error::VersionConflict(conflict) if conflict == VersionConflictError::new(
+                String::from("aggregate_id"),
+                ExpectedVersion::NoStream,
+                1
+            )
+        ));
+        event_store
+            .persist(
+                event_envelope("aggregate_id", 1, 2),
+                ExpectedVersion::Exact(0),
+            )
+            .await
+            .expect_err("expected version conflict");
+        event_store
+            .persist(
+                event_envelope("aggregate_id", 1, 2),
+                ExpectedVersion::Exact(1),
+            )
+            .await
+            .expect("expected persisted event");
+    }
+
+    #[tokio::test]
+    async fn it_rejects_duplicate_version() {
+        let event_store = InMemoryEventStore::default();
+        event_store
+            .persist(event_envelope("aggregate_id", 1, 1), ExpectedVersion::Any)
+            .await
+            .expect("expected persisted event");
+        let error = event_store
+            .persist(event_envelope("aggregate_id", 2, 1), ExpectedVersion::Any)
+            .await
+            .expect_err("expected version conflict");
+        assert!(matches!(error, Error::VersionConflict(_)));
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert_eq!(event_envelopes.len(), 1);
+        assert_eq!(event_envelopes[0].data.amount, 1);
+    }
+
+    #[tokio::test]
+    async fn it_appends_events_with_consecutive_versions() {
+        let event_store = InMemoryEventStore::default();
+        let version = event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![
+                    event_envelope("aggregate_id", 1, 0),
+                    event_envelope("aggregate_id", 2, 0),
+                ],
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected appended events");
+        assert_eq!(version, 2);
+        let version = event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![event_envelope("aggregate_id", 3, 0)],
+                ExpectedVersion::Exact(2),
+            )
+            .await
+            .expect("expected appended events");
+        assert_eq!(version, 3);
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert_eq!(
+            event_envelopes
+                .iter()
+                .map(|event_envelope| (event_envelope.version, event_envelope.data.amount))
+                .collect::<Vec<(i64, i64)>>(),
+            vec![(1, 1), (2, 2), (3, 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_appends_nothing_on_conflict() {
+        let event_store = InMemoryEventStore::default();
+        event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![event_envelope("aggregate_id", 1, 0)],
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected appended events");
+        let error = event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![
+                    event_envelope("aggregate_id", 2, 0),
+                    event_envelope("aggregate_id", 3, 0),
+                ],
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect_err("expected version conflict");
+        assert!(matches!(error, Error::VersionConflict(_)));
+        let error = event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![
+                    event_envelope("aggregate_id", 2, 0),
+                    event_envelope("other_aggregate_id", 3, 0),
+                ],
+                ExpectedVersion::Exact(1),
+            )
+            .await
+            .expect_err("expected mismatched aggregate to be rejected");
+        assert!(matches!(error, Error::InvalidArgument(_)));
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert_eq!(event_envelopes.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_upcasts_events_on_read() {
+        // Shape of `TestEvent` before it had a description.
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        struct TestEventV1 {
+            id: Uuid,
+            amount: i64,
+        }
+
+        impl EventType for TestEventV1 {
+            fn event_type(&self) -> String {
+                String::from("TestEvent")
+            }
+        }
+
+        fn add_description(mut data: serde_json::Value) -> Result<serde_json::Value, Error> {
+            data["description"] = serde_json::Value::from("Deposit");
+            Ok(data)
+        }
+
+        let event_store = InMemoryEventStore::default()
+            .with_upcasters(UpcasterRegistry::default().register("TestEvent", 1, add_description));
+        let test_event = TestEventV1 {
+            id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
+            amount: 1,
+        };
+        let event_type = test_event.event_type();
+        event_store
+            .persist(
+                EventEnvelope::new(
+                    String::from("aggregate_id"),
+                    String::from("TestAggregate"),
+                    test_event,
+                    event_type,
+                    1,
+                ),
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected persisted event");
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert_eq!(event_envelopes[0].schema_version, 2);
+        assert_eq!(event_envelopes[0].data.description, "Deposit");
+    }
+
+    #[tokio::test]
+    async fn it_decodes_events_by_event_type() {
+        #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+        struct Renamed {
+            description: String,
+        }
+
+        #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+        #[serde(untagged)]
+        enum AccountEvent {
+            Deposited(TestEvent),
+            Renamed(Renamed),
+        }
+
+        impl EventType for AccountEvent {
+            fn event_type(&self) -> String {
+                match self {
+                    AccountEvent::Deposited(_) => String::from("Deposited"),
+                    AccountEvent::Renamed(_) => String::from("Renamed"),
+                }
+            }
+
+            fn registry() -> Option<&'static EventRegistry<Self>> {
+                static REGISTRY: OnceLock<EventRegistry<AccountEvent>> = OnceLock::new();
+                Some(REGISTRY.get_or_init(|| {
+                    EventRegistry::default()
+                        .register("Deposited", AccountEvent::Deposited)
+                        .register("Renamed", AccountEvent::Renamed)
+                        .skip_unknown()
+                }))
+            }
+        }
+
+        let event_store = InMemoryEventStore::default();
+        let renamed = AccountEvent::Renamed(Renamed {
+            description: String::from("Savings"),
+        });
+        let mut closed = EventEnvelope::new(
+            String::from("aggregate_id"),
+            String::from("TestAggregate"),
+            renamed.clone(),
+            String::from("Renamed"),
+            0,
+        );
+        closed.event_type = String::from("Closed");
+        event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![
+                    EventEnvelope::new(
+                        String::from("aggregate_id"),
+                        String::from("TestAggregate"),
+                        renamed.clone(),
+                        renamed.event_type(),
+                        0,
+                    ),
+                    closed,
+                ],
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected appended events");
+        let event_envelopes: Vec<EventEnvelope<AccountEvent>> = event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert_eq!(event_envelopes.len(), 1);
+        assert_eq!(event_envelopes[0].data, renamed);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[tokio::test]
+    async fn it_reads_events_written_with_different_content_types() {
+        let event_store = InMemoryEventStore::default();
+        event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![event_envelope("aggregate_id", 1, 0)],
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected appended events");
+        event_store
+            .clone()
+            .with_codec(ContentType::MessagePack)
+            .append(
+                &String::from("aggregate_id"),
+                vec![event_envelope("aggregate_id", 2, 0)],
+                ExpectedVersion::Exact(1),
+            )
+            .await
+            .expect("expected appended events");
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert_eq!(
+            event_envelopes
+                .iter()
+                .map(|event_envelope| event_envelope.data.amount)
+                .collect::<Vec<i64>>(),
+            vec![1, 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_encodes_events_with_custom_codec() {
+        // JSON with its bytes reversed, so that reading it with another codec fails.
+        #[derive(Debug, Clone, Copy)]
+        struct ReversedJsonCodec;
+
+        impl Codec for ReversedJsonCodec {
+            fn content_type(&self) -> &str {
+                "application/vnd.reversed+json"
+            }
+
+            fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+                let mut bytes = serde_json::to_vec(value)?;
+                bytes.reverse();
+                Ok(bytes)
+            }
+
+            fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+                let mut bytes = bytes.to_vec();
+                bytes.reverse();
+                Ok(serde_json::from_slice(&bytes)?)
+            }
+        }
+
+        let event_store = InMemoryEventStore::default();
+        event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![event_envelope("aggregate_id", 1, 0)],
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected appended events");
+        let custom_event_store = event_store.clone().with_codec(ReversedJsonCodec);
+        custom_event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![event_envelope("aggregate_id", 2, 0)],
+                ExpectedVersion::Exact(1),
+            )
+            .await
+            .expect("expected appended events");
+
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = custom_event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert_eq!(
+            event_envelopes
+                .iter()
+                .map(|event_envelope| event_envelope.data.amount)
+                .collect::<Vec<i64>>(),
+            vec![1, 2]
+        );
+        assert!(event_store
+            .read::<TestEvent>(&String::from("aggregate_id"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn it_reads_all_events_in_stored_order() {
+        let event_store = InMemoryEventStore::default();
+        event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![
+                    event_envelope("aggregate_id", 1, 0),
+                    event_envelope("aggregate_id", 2, 0),
+                ],
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected appended events");
+        event_store
+            .persist(
+                event_envelope("other_aggregate_id", 3, 1),
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected persisted event");
+        event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![event_envelope("aggregate_id", 4, 0)],
+                ExpectedVersion::Exact(2),
+            )
+            .await
+            .expect("expected appended events");
+
+        let event_batch: EventBatch<TestEvent> = event_store
+            .read_all(1, 10, &EventFilter::default())
+            .await
+            .expect("expected events");
+        assert_eq!(event_batch.next_position, 5);
+        assert_eq!(
+            event_batch
+                .event_envelopes
+                .iter()
+                .map(|event_envelope| (event_envelope.position, event_envelope.data.amount))
+                .collect::<Vec<(i64, i64)>>(),
+            vec![(1, 1), (2, 2), (3, 3), (4, 4)]
+        );
+        let event_batch: EventBatch<TestEvent> = event_store
+            .read_all(2, 2, &EventFilter::default())
+            .await
+            .expect("expected events");
+        assert_eq!(event_batch.next_position, 4);
+        assert_eq!(
+            event_batch
+                .event_envelopes
+                .iter()
+                .map(|event_envelope| event_envelope.position)
+                .collect::<Vec<i64>>(),
+            vec![2, 3]
+        );
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert_eq!(
+            event_envelopes
+                .iter()
+                .map(|event_envelope| event_envelope.position)
+                .collect::<Vec<i64>>(),
+            vec![1, 2, 4]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reads_all_events_matching_filter() {
+        let event_store = InMemoryEventStore::default();
+        let mut other_event_envelope = event_envelope("other_aggregate_id", 2, 1);
+        other_event_envelope.aggregate_type = String::from("OtherAggregate");
+        event_store
+            .persist(
+                event_envelope("aggregate_id", 1, 1),
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected persisted event");
+        event_store
+            .persist(other_event_envelope, ExpectedVersion::NoStream)
+            .await
+            .expect("expected persisted event");
+        event_store
+            .persist(
+                event_envelope("aggregate_id", 3, 2),
+                ExpectedVersion::Exact(1),
+            )
+            .await
+            .expect("expected persisted event");
+
+        let event_batch: EventBatch<TestEvent> = event_store
+            .read_all(
+                0,
+                10,
+                &EventFilter::default().with_aggregate_type("OtherAggregate"),
+            )
+            .await
+            .expect("expected events");
+        assert_eq!(event_batch.event_envelopes.len(), 1);
+        assert_eq!(event_batch.event_envelopes[0].position, 2);
+        assert_eq!(event_batch.next_position, 4);
+        let event_batch: EventBatch<TestEvent> = event_store
+            .read_all(0, 10, &EventFilter::default().with_event_type("OtherEvent"))
+            .await
+            .expect("expected events");
+        assert!(event_batch.event_envelopes.is_empty());
+        assert_eq!(event_batch.next_position, 4);
+    }
+
+    #[tokio::test]
+    async fn it_streams_events_page_by_page() {
+        let event_store = InMemoryEventStore::default().with_page_size(2);
+        event_store
+            .append(
+                &String::from("aggregate_id"),
+                (1..=5)
+                    .map(|amount| event_envelope("aggregate_id", amount, 0))
+                    .collect(),
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected appended events");
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .stream_from(&String::from("aggregate_id"), 2)
+            .try_collect()
+            .await
+            .expect("expected events");
+        assert_eq!(
+            event_envelopes
+                .iter()
+                .map(|event_envelope| event_envelope.data.amount)
+                .collect::<Vec<i64>>(),
+            vec![2, 3, 4, 5]
+        );
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .stream(&String::from("other_aggregate_id"))
+            .try_collect()
+            .await
+            .expect("expected events");
+        assert!(event_envelopes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_reads_part_of_stream() {
+        let event_store = InMemoryEventStore::default();
+        event_store
+            .append(
+                &String::from("aggregate_id"),
+                (1..=5)
+                    .map(|amount| event_envelope("aggregate_id", amount, 0))
+                    .collect(),
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected appended events");
+        let versions = |event_envelopes: Vec<EventEnvelope<TestEvent>>| {
+            event_envelopes
+                .iter()
+                .map(|event_envelope| event_envelope.version)
+                .collect::<Vec<i64>>()
+        };
+        assert_eq!(
+            versions(
+                event_store
+                    .read_range(&String::from("aggregate_id"), 2, 4)
+                    .await
+                    .expect("expected events")
+            ),
+            vec![2, 3, 4]
+        );
+        assert_eq!(
+            versions(
+                event_store
+                    .read_to_version(&String::from("aggregate_id"), 2)
+                    .await
+                    .expect("expected events")
+            ),
+            vec![1, 2]
+        );
+        assert_eq!(
+            versions(
+                event_store
+                    .read_backward(&String::from("aggregate_id"), 4, 3)
+                    .await
+                    .expect("expected events")
+            ),
+            vec![4, 3, 2]
+        );
+        assert_eq!(
+            versions(
+                event_store
+                    .read_latest(&String::from("aggregate_id"), 2)
+                    .await
+                    .expect("expected events")
+            ),
+            vec![4, 5]
+        );
+    }
+
+    // Append three events to `aggregate_id` and one to `other_aggregate_id`.
+    async fn append_two_streams(event_store: &InMemoryEventStore) {
+        event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![
+                    event_envelope("aggregate_id", 1, 0),
+                    event_envelope("aggregate_id", 2, 0),
+                    event_envelope("aggregate_id", 3, 0),
+                ],
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected appended events");
+        event_store
+            .persist(
+                event_envelope("other_aggregate_id", 4, 1),
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect("expected persisted event");
+    }
+
+    async fn read_all_amounts(event_store: &InMemoryEventStore) -> Vec<i64> {
+        event_store
+            .read_all::<TestEvent>(0, 10, &EventFilter::default())
+            .await
+            .expect("expected events")
+            .event_envelopes
+            .into_iter()
+            .map(|event_envelope| event_envelope.data.amount)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn it_hides_soft_deleted_stream() {
+        let event_store = InMemoryEventStore::default();
+        append_two_streams(&event_store).await;
+        let error = event_store
+            .soft_delete(&String::from("aggregate_id"), ExpectedVersion::Exact(2))
+            .await
+            .expect_err("expected version conflict");
+        assert!(matches!(error, Error::VersionConflict(_)));
+        event_store
+            .soft_delete(&String::from("aggregate_id"), ExpectedVersion::Exact(3))
+            .await
+            .expect("expected deleted stream");
+
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert!(event_envelopes.is_empty());
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .stream(&String::from("aggregate_id"))
+            .try_collect()
+            .await
+            .expect("expected events");
+        assert!(event_envelopes.is_empty());
+        assert_eq!(read_all_amounts(&event_store).await, vec![4]);
+        let error = event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![event_envelope("aggregate_id", 5, 0)],
+                ExpectedVersion::Any,
+            )
+            .await
+            .expect_err("expected deleted stream");
+        assert!(matches!(error, Error::StreamDeleted(_)));
+        let error = event_store
+            .soft_delete(&String::from("aggregate_id"), ExpectedVersion::Any)
+            .await
+            .expect_err("expected deleted stream");
+        assert!(matches!(error, Error::StreamDeleted(_)));
+    }
+
+    #[tokio::test]
+    async fn it_erases_hard_deleted_stream() {
+        let event_store = InMemoryEventStore::default();
+        append_two_streams(&event_store).await;
+        event_store
+            .soft_delete(&String::from("aggregate_id"), ExpectedVersion::Any)
+            .await
+            .expect("expected deleted stream");
+        event_store
+            .hard_delete(&String::from("aggregate_id"), ExpectedVersion::Exact(0))
+            .await
+            .expect("expected erased stream");
+
+        assert!(!event_store
+            .streams
+            .read()
+            .expect("expected streams")
+            .events
+            .contains_key("aggregate_id"));
+        assert_eq!(read_all_amounts(&event_store).await, vec![4]);
+        let error = event_store
+            .persist(
+                event_envelope("aggregate_id", 5, 1),
+                ExpectedVersion::NoStream,
+            )
+            .await
+            .expect_err("expected deleted stream");
+        assert!(matches!(error, Error::StreamDeleted(_)));
+    }
+
+    #[tokio::test]
+    async fn it_truncates_stream_before_version() {
+        let event_store = InMemoryEventStore::default();
+        append_two_streams(&event_store).await;
+        event_store
+            .truncate_before(&String::from("aggregate_id"), 3)
+            .await
+            .expect("expected truncated stream");
+
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert_eq!(event_envelopes.len(), 1);
+        assert_eq!(event_envelopes[0].version, 3);
+        assert_eq!(read_all_amounts(&event_store).await, vec![3, 4]);
+        event_store
+            .truncate_before(&String::from("aggregate_id"), 10)
+            .await
+            .expect("expected truncated stream");
+        let version = event_store
+            .append(
+                &String::from("aggregate_id"),
+                vec![event_envelope("aggregate_id", 5, 0)],
+                ExpectedVersion::Exact(3),
+            )
+            .await
+            .expect("expected appended events");
+        assert_eq!(version, 4);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_truncating_deleted_stream() {
+        let event_store = InMemoryEventStore::default();
+        append_two_streams(&event_store).await;
+        event_store
+            .soft_delete(&String::from("aggregate_id"), ExpectedVersion::Any)
+            .await
+            .expect("expected deleted stream");
+
+        let error = event_store
+            .truncate_before(&String::from("aggregate_id"), 3)
+            .await
+            .expect_err("expected deleted stream");
+        assert!(matches!(error, Error::StreamDeleted(_)));
+        // Soft deleted streams keep their events.
+        assert_eq!(
+            event_store.streams.read().expect("expected streams").events["aggregate_id"].len(),
+            3
+        );
+    }
+
+    // Persist events two hours old at versions 1 and 2, and a fresh one at version 3.
+    async fn persist_aging_stream(event_store: &InMemoryEventStore) {
+        for version in 1..=3 {
+            let mut event_envelope = event_envelope("aggregate_id", version, version);
+            if version < 3 {
+                event_envelope.timestamp = Utc::now() - chrono::Duration::hours(2);
+            }
+            event_store
+                .persist(event_envelope, ExpectedVersion::Exact(version - 1))
+                .await
+                .expect("expected persisted event");
+        }
+    }
+
+    #[tokio::test]
+    async fn it_applies_stream_retention_on_read() {
+        let event_store = InMemoryEventStore::default();
+        persist_aging_stream(&event_store).await;
+        let metadata = StreamMetadata::default()
+            .with_max_count(2)
+            .with_acl("telemetry")
+            .with_custom(serde_json::json!({ "owner": "metrics" }));
+        event_store
+            .write_stream_metadata(&String::from("aggregate_id"), metadata.clone())
+            .await
+            .expect("expected written metadata");
+        assert_eq!(
+            event_store
+                .read_stream_metadata(&String::from("aggregate_id"))
+                .await
+                .expect("expected metadata"),
+            metadata
+        );
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .read(&String::from("aggregate_id"))
+            .await
+            .expect("expected events");
+        assert_eq!(
+            event_envelopes
+                .iter()
+                .map(|event_envelope| event_envelope.version)
+                .collect::<Vec<i64>>(),
+            vec![2, 3]
+        );
+
+        event_store
+            .write_stream_metadata(
+                &String::from("aggregate_id"),
+                StreamMetadata::default().with_max_age(Duration::from_secs(3600)),
+            )
+            .await
+            .expect("expected written metadata");
+        let event_envelopes: Vec<EventEnvelope<TestEvent>> = event_store
+            .stream(&String::from("aggregate_id"))
+            .try_collect()
+            .await
+            .expect("expected events");
+        assert_eq!(event_envelopes.len(), 1);
+        assert_eq!(event_envelopes[0].version, 3);
+        assert_eq!(read_all_amounts(&event_store).await, vec![3]);
+    }
+
+    #[tokio::test]
+    async fn it_scavenges_expired_events() {
+        let event_store = InMemoryEventStore::default();
+        persist_aging_stream(&event_store).await;
+        let scavenged = event_store.scavenge().await.expect("expected scavenge");
+        assert_eq!(scavenged, 0);
+        event_store
+            .write_stream_metadata(
+                &String::from("aggregate_id"),
+                StreamMetadata::default().with_max_age(Duration::from_secs(60)),
+            )
+            .await
+            .expect("expected written metadata");
+        let scavenged = Scavenger::new(event_store.clone(), Duration::from_secs(60))
+            .scavenge()
+            .await
+            .expect("expected scavenge");
+        assert_eq!(scavenged, 2);
+        let streams = event_store.streams.read().expect("expected streams");
+        assert_eq!(streams.events["aggregate_id"].len(), 1);
+    }
+}
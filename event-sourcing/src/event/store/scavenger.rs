@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use crate::event::store::EventStore;
+use crate::runtime::Sleep;
+use crate::Error;
+
+/// Background task that periodically erases the events that have expired according to the
+/// metadata of their stream.
+///
+/// # Example
+///
+/// ```
+/// # use std::time::Duration;
+/// # use event_sourcing::event::store::in_memory::InMemoryEventStore;
+/// # use event_sourcing::event::store::scavenger::Scavenger;
+///
+/// let scavenger = Scavenger::new(InMemoryEventStore::default(), Duration::from_secs(60));
+/// // Spawn it on the runtime of the application, e.g. `tokio::spawn(run)`.
+/// let run = scavenger.run(|duration| Box::pin(tokio::time::sleep(duration)));
+///
+/// # drop(run);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Scavenger<E> {
+    event_store: E,
+    // Time to wait between two scavenges.
+    interval: Duration,
+}
+
+impl<E: EventStore> Scavenger<E> {
+    pub fn new(event_store: E, interval: Duration) -> Self {
+        Self {
+            event_store,
+            interval,
+        }
+    }
+
+    /// Scavenge the event store once and return how many events were erased.
+    pub async fn scavenge(&self) -> Result<usize, Error> {
+        self.event_store.scavenge().await
+    }
+
+    /// Scavenge the event store every interval, starting right away.  Only returns when a scavenge
+    /// fails with an error that cannot be retried.
+    pub async fn run(self, sleep: Sleep) -> Result<(), Error> {
+        loop {
+            match self.scavenge().await {
+                Err(error) if !error.is_retryable() => return Err(error),
+                _ => sleep(self.interval).await,
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use futures::channel::mpsc::{self, Sender};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::event::envelope::EventEnvelope;
+use crate::event::store::{EventBatch, EventFilter, EventStore};
+use crate::event::EventType;
+use crate::runtime::Sleep;
+use crate::Error;
+
+/// Wakes up the subscribers of an event store whenever new events have been stored.
+///
+/// Clones share the same subscribers.
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    subscribers: Arc<Mutex<Vec<Sender<()>>>>,
+}
+
+impl Notifier {
+    /// Stream of wake-ups for the calls to `notify` made after subscribing.  At most one wake-up is
+    /// pending at a time, so a subscriber that is busy is woken up once for every call made meanwhile.
+    pub fn subscribe(&self) -> BoxStream<'static, ()> {
+        // Every sender is guaranteed a slot on top of the buffer, so this holds a single wake-up.
+        let (sender, receiver) = mpsc::channel(0);
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver.boxed()
+    }
+
+    /// Wake up every subscriber, forgetting those that have gone away.
+    pub fn notify(&self) {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain_mut(|sender| match sender.try_send(()) {
+                Ok(()) => true,
+                Err(error) => error.is_full(),
+            });
+    }
+}
+
+/// Subscription to the events of every aggregate that replays the events stored from a global
+/// position on, then keeps delivering new events as they are stored.
+///
+/// Events are read with `EventStore::read_all` a batch at a time.  Once the subscription has caught
+/// up, it waits for the notifications of the store, or polls it at the configured interval when the
+/// store cannot notify.  Without either, the stream ends once it has caught up.
+///
+/// Events are delivered in the order of their positions.  Stores that reserve positions before
+/// writing hold back `read_all` at a position that is still being written, so an event committed
+/// after a later one is not skipped.
+///
+/// # Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use futures::StreamExt;
+/// # use uuid::Uuid;
+/// # use serde::{Deserialize, Serialize};
+/// # use event_sourcing::event::envelope::EventEnvelope;
+/// # use event_sourcing::event::store::{EventStore, ExpectedVersion};
+/// # use event_sourcing::event::store::in_memory::InMemoryEventStore;
+/// # use event_sourcing::event::store::subscription::CatchUpSubscription;
+/// # use event_sourcing::event::EventType;
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     id: Uuid,
+/// #     amount: i64,
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// # }
+///
+/// # futures::executor::block_on(async {
+/// # let test_event = TestEvent {
+/// #     id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
+/// #     amount: 1,
+/// #     description: String::from("Deposit"),
+/// # };
+/// let event_store = InMemoryEventStore::default();
+/// let mut subscription = CatchUpSubscription::new(event_store.clone(), 1).stream::<TestEvent>();
+/// event_store
+///     .append(
+///         &String::from("aggregate_id"),
+///         vec![EventEnvelope::new(
+///             String::from("aggregate_id"),
+///             String::from("TestAggregate"),
+///             test_event.clone(),
+///             test_event.event_type(),
+///             1,
+///         )],
+///         ExpectedVersion::NoStream,
+///     )
+///     .await
+///     .expect("expected appended events");
+/// let event_envelope = subscription
+///     .next()
+///     .await
+///     .expect("expected event")
+///     .expect("expected no error");
+///
+/// # assert_eq!(event_envelope.position, 1);
+/// # assert_eq!(event_envelope.data, test_event);
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct CatchUpSubscription<E> {
+    event_store: E,
+    // Global position of the first event to deliver.
+    from_position: i64,
+    filter: EventFilter,
+    // Number of events read at a time.
+    batch_size: usize,
+    // Interval at which stores that cannot notify are polled once caught up.
+    polling: Option<(Duration, Sleep)>,
+}
+
+impl<E: EventStore + 'static> CatchUpSubscription<E> {
+    pub fn new(event_store: E, from_position: i64) -> Self {
+        Self {
+            event_store,
+            from_position,
+            filter: EventFilter::default(),
+            batch_size: 500,
+            polling: None,
+        }
+    }
+
+    /// Only deliver events that match the filter.
+    pub fn with_filter(mut self, filter: EventFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Read events `batch_size` at a time.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Poll the store every interval once caught up when it cannot notify the subscription.
+    pub fn with_polling(mut self, interval: Duration, sleep: Sleep) -> Self {
+        self.polling = Some((interval, sleep));
+        self
+    }
+
+    /// Stream the events, replaying the stored ones before waiting for new ones.
+    pub fn stream<Event: EventType + Serialize + DeserializeOwned>(
+        self,
+    ) -> BoxStream<'static, Result<EventEnvelope<Event>, Error>> {
+        // Subscribe before the first read so that no event stored in between is missed.
+        let notifications = self.event_store.notifications();
+        let position = self.from_position;
+        stream::try_unfold(
+            (self, position, notifications),
+            |(subscription, mut position, mut notifications)| async move {
+                loop {
+                    let event_batch: EventBatch<Event> = subscription
+                        .event_store
+                        .read_all(position, subscription.batch_size, &subscription.filter)
+                        .await?;
+                    let scanned = event_batch.next_position > position;
+                    // Resume after the events the filter skipped as well.
+                    position = event_batch.next_position;
+                    if !event_batch.event_envelopes.is_empty() {
+                        return Ok::<_, Error>(Some((
+                            stream::iter(event_batch.event_envelopes.into_iter().map(Ok)),
+                            (subscription, position, notifications),
+                        )));
+                    }
+                    // The store stopped scanning before reaching the end of the log.
+                    if scanned {
+                        continue;
+                    }
+                    match (&mut notifications, subscription.polling) {
+                        (Some(notifications), _) => {
+                            if notifications.next().await.is_none() {
+                                return Ok(None);
+                            }
+                        }
+                        (None, Some((interval, sleep))) => sleep(interval).await,
+                        (None, None) => return Ok(None),
+                    }
+                }
+            },
+        )
+        .try_flatten()
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::*;
+    use crate::event::store::in_memory::InMemoryEventStore;
+    use crate::fixtures::{append, TestEvent};
+
+    #[tokio::test]
+    async fn it_merges_pending_wake_ups() {
+        let notifier = Notifier::default();
+        let mut notifications = notifier.subscribe();
+        for _ in 0..3 {
+            notifier.notify();
+        }
+        assert_eq!(notifications.next().await, Some(()));
+        assert_eq!(notifications.next().now_or_never(), None);
+
+        notifier.notify();
+        assert_eq!(notifications.next().await, Some(()));
+    }
+
+    #[tokio::test]
+    async fn it_catches_up_then_delivers_live_events() {
+        let event_store = InMemoryEventStore::default();
+        append(
+            &event_store,
+            "TestAggregate",
+            "TestAggregate",
+            vec![1, 2, 3],
+        )
+        .await;
+        append(&event_store, "OtherAggregate", "OtherAggregate", vec![4]).await;
+        let mut subscription = CatchUpSubscription::new(event_store.clone(), 2)
+            .with_filter(EventFilter::default().with_aggregate_type("TestAggregate"))
+            .with_batch_size(1)
+            .stream::<TestEvent>();
+        let mut amounts = Vec::new();
+        for _ in 0..2 {
+            let event_envelope = subscription
+                .next()
+                .await
+                .expect("expected event")
+                .expect("expected no error");
+            amounts.push(event_envelope.data.amount);
+        }
+        assert_eq!(amounts, vec![2, 3]);
+
+        let live_event_store = event_store.clone();
+        let live = tokio::spawn(async move {
+            append(
+                &live_event_store,
+                "OtherAggregate",
+                "OtherAggregate",
+                vec![5],
+            )
+            .await;
+            append(&live_event_store, "TestAggregate", "TestAggregate", vec![6]).await;
+        });
+        let event_envelope = subscription
+            .next()
+            .await
+            .expect("expected event")
+            .expect("expected no error");
+        live.await.expect("expected appended events");
+        assert_eq!(event_envelope.data.amount, 6);
+        assert_eq!(event_envelope.position, 6);
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::Error;
+
+/// Transform moving the JSON data of an event from one schema version to the next.
+pub type Upcast = fn(data: Value) -> Result<Value, Error>;
+
+/// Registry of transforms that bring stored events up to the current shape of their Rust type.
+///
+/// Upcasters are keyed by event type and the schema version they upcast from.  When an event is
+/// read, the upcasters are chained until no upcaster is registered for its version, so an event
+/// written at version 1 goes through the `1 -> 2` and `2 -> 3` upcasters before it is deserialized.
+///
+/// # Example
+///
+/// ```
+/// # use serde_json::{json, Value};
+/// # use event_sourcing::Error;
+/// # use event_sourcing::event::upcaster::UpcasterRegistry;
+///
+/// fn rename_amount(mut data: Value) -> Result<Value, Error> {
+///     let amount = data["value"].take();
+///     data["amount"] = amount;
+///     Ok(data)
+/// }
+///
+/// let upcasters = UpcasterRegistry::default().register("TestEvent", 1, rename_amount);
+/// let (schema_version, data) = upcasters
+///     .upcast("TestEvent", 1, json!({ "value": 1 }))
+///     .expect("expected upcasted data");
+///
+/// # assert_eq!(schema_version, 2);
+/// # assert_eq!(data["amount"], 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct UpcasterRegistry {
+    upcasters: HashMap<(String, i64), Upcast>,
+}
+
+impl UpcasterRegistry {
+    /// Register an upcaster moving events of `event_type` from `schema_version` to
+    /// `schema_version + 1`.
+    pub fn register(
+        mut self,
+        event_type: impl Into<String>,
+        schema_version: i64,
+        upcast: Upcast,
+    ) -> Self {
+        self.upcasters
+            .insert((event_type.into(), schema_version), upcast);
+        self
+    }
+
+    /// Whether no upcaster has been registered.
+    pub fn is_empty(&self) -> bool {
+        self.upcasters.is_empty()
+    }
+
+    /// Apply every upcaster registered for the event type from `schema_version` onwards and return
+    /// the resulting schema version along with the upcasted data.
+    pub fn upcast(
+        &self,
+        event_type: &str,
+        mut schema_version: i64,
+        mut data: Value,
+    ) -> Result<(i64, Value), Error> {
+        while let Some(upcast) = self
+            .upcasters
+            .get(&(String::from(event_type), schema_version))
+        {
+            data = upcast(data)?;
+            schema_version += 1;
+        }
+        Ok((schema_version, data))
+    }
+
+    /// Upcast the data of a serialized event envelope in place.
+    pub fn upcast_envelope(&self, mut event_envelope: Value) -> Result<Value, Error> {
+        if self.upcasters.is_empty() {
+            return Ok(event_envelope);
+        }
+        let event_type = event_envelope["event_type"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| Error::serialization("event envelope is missing its `event_type`"))?;
+        let schema_version = event_envelope["schema_version"].as_i64().unwrap_or(1);
+        let (schema_version, data) =
+            self.upcast(&event_type, schema_version, event_envelope["data"].take())?;
+        event_envelope["schema_version"] = Value::from(schema_version);
+        event_envelope["data"] = data;
+        Ok(event_envelope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn add_currency(mut data: Value) -> Result<Value, Error> {
+        data["currency"] = Value::from("EUR");
+        Ok(data)
+    }
+
+    fn rename_amount(mut data: Value) -> Result<Value, Error> {
+        let object = data
+            .as_object_mut()
+            .ok_or_else(|| Error::serialization("expected an object"))?;
+        let amount = object.remove("value").unwrap_or_default();
+        object.insert(String::from("amount"), amount);
+        Ok(data)
+    }
+
+    #[test]
+    fn it_chains_upcasters() {
+        let upcasters = UpcasterRegistry::default()
+            .register("TestEvent", 2, add_currency)
+            .register("TestEvent", 1, rename_amount);
+        let (schema_version, data) = upcasters
+            .upcast("TestEvent", 1, json!({ "value": 1 }))
+            .expect("expected upcasted data");
+        assert_eq!(schema_version, 3);
+        assert_eq!(data, json!({ "amount": 1, "currency": "EUR" }));
+    }
+
+    #[test]
+    fn it_only_upcasts_matching_event_types_and_versions() {
+        let upcasters = UpcasterRegistry::default().register("TestEvent", 1, rename_amount);
+        let (schema_version, data) = upcasters
+            .upcast("OtherEvent", 1, json!({ "value": 1 }))
+            .expect("expected data");
+        assert_eq!(schema_version, 1);
+        assert_eq!(data, json!({ "value": 1 }));
+        let (schema_version, _) = upcasters
+            .upcast("TestEvent", 2, json!({ "amount": 1 }))
+            .expect("expected data");
+        assert_eq!(schema_version, 2);
+    }
+
+    #[test]
+    fn it_upcasts_envelopes_without_schema_version() {
+        let upcasters = UpcasterRegistry::default().register("TestEvent", 1, rename_amount);
+        let event_envelope = upcasters
+            .upcast_envelope(json!({ "event_type": "TestEvent", "data": { "value": 1 } }))
+            .expect("expected upcasted envelope");
+        assert_eq!(event_envelope["schema_version"], 2);
+        assert_eq!(event_envelope["data"]["amount"], 1);
+    }
+}
@@ -0,0 +1,337 @@
+//! Event, aggregate and command shared by the unit tests of the crate.
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use futures::stream::BoxStream;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::aggregate::{Aggregate, Decider};
+use crate::event::envelope::EventEnvelope;
+use crate::event::metadata::StreamMetadata;
+use crate::event::store::in_memory::InMemoryEventStore;
+use crate::event::store::{EventBatch, EventFilter, EventStore, ExpectedVersion};
+use crate::event::EventType;
+use crate::Error;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct TestEvent {
+    pub(crate) id: Uuid,
+    pub(crate) amount: i64,
+    pub(crate) description: String,
+}
+
+impl EventType for TestEvent {
+    fn event_type(&self) -> String {
+        String::from("TestEvent")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct TestAggregate {
+    pub(crate) id: Uuid,
+    pub(crate) total: i64,
+}
+
+impl Aggregate for TestAggregate {
+    type AggregateID = Uuid;
+    type Event = TestEvent;
+    type Error = Error;
+
+    fn aggregate_type() -> String {
+        String::from("TestAggregate")
+    }
+
+    fn aggregate_id(&self) -> &Self::AggregateID {
+        &self.id
+    }
+
+    fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error> {
+        match state {
+            None => Ok(Self {
+                id: event.id,
+                total: event.amount,
+            }),
+            Some(mut state) => {
+                state.total += event.amount;
+                Ok(state)
+            }
+        }
+    }
+
+    fn apply_all(events: Vec<Self::Event>) -> Result<Self, Self::Error> {
+        events
+            .into_iter()
+            .try_fold(None, |state, event| Self::apply(state, event).map(Some))?
+            .ok_or_else(|| Error::from("Aggregate must not be None"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum TestCommand {
+    Deposit { id: Uuid, amount: i64 },
+    Withdraw { id: Uuid, amount: i64 },
+}
+
+impl TestCommand {
+    pub(crate) fn id(&self) -> Uuid {
+        match self {
+            TestCommand::Deposit { id, .. } | TestCommand::Withdraw { id, .. } => *id,
+        }
+    }
+}
+
+impl Decider for TestAggregate {
+    type Command = TestCommand;
+
+    fn decide(
+        state: Option<&Self>,
+        command: Self::Command,
+    ) -> Result<Vec<Self::Event>, Self::Error> {
+        match command {
+            TestCommand::Deposit { amount, .. } if amount <= 0 => Err(Error::InvalidArgument(
+                String::from("Deposit must be positive"),
+            )),
+            TestCommand::Deposit { id, amount } => Ok(vec![TestEvent {
+                id,
+                amount,
+                description: String::from("Deposit"),
+            }]),
+            TestCommand::Withdraw { id, amount } => match state {
+                Some(state) if state.total >= amount => Ok(vec![TestEvent {
+                    id,
+                    amount: -amount,
+                    description: String::from("Withdrawal"),
+                }]),
+                _ => Err(Error::InvalidArgument(String::from("Insufficient funds"))),
+            },
+        }
+    }
+}
+
+pub(crate) fn test_id() -> Uuid {
+    Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid")
+}
+
+// Deposit of the amount into the test aggregate.
+pub(crate) fn test_event(amount: i64) -> TestEvent {
+    TestEvent {
+        id: test_id(),
+        amount,
+        description: String::from("Deposit"),
+    }
+}
+
+pub(crate) fn event_envelope(
+    aggregate_id: &str,
+    amount: i64,
+    version: i64,
+) -> EventEnvelope<TestEvent> {
+    let test_event = test_event(amount);
+    let event_type = test_event.event_type();
+    EventEnvelope::new(
+        String::from(aggregate_id),
+        TestAggregate::aggregate_type(),
+        test_event,
+        event_type,
+        version,
+    )
+}
+
+// Append deposits of the amounts to the stream of the aggregate, whatever its current version.
+pub(crate) async fn append(
+    event_store: &InMemoryEventStore,
+    aggregate_type: &str,
+    aggregate_id: &str,
+    amounts: Vec<i64>,
+) {
+    let event_envelopes = amounts
+        .into_iter()
+        .map(|amount| {
+            let test_event = test_event(amount);
+            let event_type = test_event.event_type();
+            EventEnvelope::new(
+                String::from(aggregate_id),
+                String::from(aggregate_type),
+                test_event,
+                event_type,
+                0,
+            )
+        })
+        .collect();
+    event_store
+        .append(
+            &aggregate_id.to_string(),
+            event_envelopes,
+            ExpectedVersion::Any,
+        )
+        .await
+        .expect("expected appended events");
+}
+
+// Event store in which another writer appends a copy of the next `conflicts` batches right before
+// them, so that the in-memory store rejects those appends with a version conflict.
+#[derive(Debug, Clone)]
+pub(crate) struct ConflictingEventStore {
+    event_store: InMemoryEventStore,
+    conflicts: Arc<AtomicUsize>,
+    // Number of appends attempted through the store, including the rejected ones.
+    appends: Arc<AtomicUsize>,
+}
+
+impl ConflictingEventStore {
+    pub(crate) fn new(event_store: InMemoryEventStore, conflicts: usize) -> Self {
+        Self {
+            event_store,
+            conflicts: Arc::new(AtomicUsize::new(conflicts)),
+            appends: Arc::default(),
+        }
+    }
+
+    pub(crate) fn appends(&self) -> usize {
+        self.appends.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait::async_trait]
+impl EventStore for ConflictingEventStore {
+    async fn read<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        self.event_store.read(aggregate_id).await
+    }
+
+    async fn read_from<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        version: i64,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        self.event_store.read_from(aggregate_id, version).await
+    }
+
+    async fn read_range<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        from_version: i64,
+        to_version: i64,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        self.event_store
+            .read_range(aggregate_id, from_version, to_version)
+            .await
+    }
+
+    async fn read_backward<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        version: i64,
+        limit: usize,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        self.event_store
+            .read_backward(aggregate_id, version, limit)
+            .await
+    }
+
+    fn stream_from<'a, Event: EventType + Serialize + DeserializeOwned>(
+        &'a self,
+        aggregate_id: &'a String,
+        version: i64,
+    ) -> BoxStream<'a, Result<EventEnvelope<Event>, Error>> {
+        self.event_store.stream_from(aggregate_id, version)
+    }
+
+    async fn persist<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        event_envelope: EventEnvelope<Event>,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        self.event_store
+            .persist(event_envelope, expected_version)
+            .await
+    }
+
+    async fn append<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        event_envelopes: Vec<EventEnvelope<Event>>,
+        expected_version: ExpectedVersion,
+    ) -> Result<i64, Error> {
+        self.appends.fetch_add(1, Ordering::SeqCst);
+        let conflict = self
+            .conflicts
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |conflicts| {
+                conflicts.checked_sub(1)
+            })
+            .is_ok();
+        if conflict {
+            self.event_store
+                .append(aggregate_id, event_envelopes.clone(), ExpectedVersion::Any)
+                .await?;
+        }
+        self.event_store
+            .append(aggregate_id, event_envelopes, expected_version)
+            .await
+    }
+
+    async fn read_version(&self, aggregate_id: &String) -> Result<i64, Error> {
+        self.event_store.read_version(aggregate_id).await
+    }
+
+    async fn read_all<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        from_position: i64,
+        limit: usize,
+        filter: &EventFilter,
+    ) -> Result<EventBatch<Event>, Error> {
+        self.event_store
+            .read_all(from_position, limit, filter)
+            .await
+    }
+
+    async fn soft_delete(
+        &self,
+        aggregate_id: &String,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        self.event_store
+            .soft_delete(aggregate_id, expected_version)
+            .await
+    }
+
+    async fn hard_delete(
+        &self,
+        aggregate_id: &String,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        self.event_store
+            .hard_delete(aggregate_id, expected_version)
+            .await
+    }
+
+    async fn truncate_before(&self, aggregate_id: &String, version: i64) -> Result<(), Error> {
+        self.event_store
+            .truncate_before(aggregate_id, version)
+            .await
+    }
+
+    async fn read_stream_metadata(&self, aggregate_id: &String) -> Result<StreamMetadata, Error> {
+        self.event_store.read_stream_metadata(aggregate_id).await
+    }
+
+    async fn write_stream_metadata(
+        &self,
+        aggregate_id: &String,
+        metadata: StreamMetadata,
+    ) -> Result<(), Error> {
+        self.event_store
+            .write_stream_metadata(aggregate_id, metadata)
+            .await
+    }
+
+    async fn scavenge(&self) -> Result<usize, Error> {
+        self.event_store.scavenge().await
+    }
+}
@@ -1,8 +1,13 @@
 pub mod aggregate;
+pub mod codec;
 pub mod command_handler;
+pub mod error;
 pub mod event;
+#[cfg(test)]
+mod fixtures;
 pub mod projection;
 pub mod query_handler;
+pub mod runtime;
 pub mod snapshot;
 
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
+pub use error::Error;
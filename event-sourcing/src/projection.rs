@@ -1,9 +1,30 @@
+pub mod checkpoint;
+pub mod repository;
+pub mod runner;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::event::envelope::EventEnvelope;
+use crate::event::store::EventFilter;
+use crate::event::EventType;
+
 pub trait Projection: Sized + Send + Sync + Clone {
-    type Event: Send + Sync + Clone;
+    type Event: EventType + Serialize + DeserializeOwned;
     type Error: Send + Sync;
 
-    // Apply event to projection.
-    fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error>;
-    // Rebuild the projection by clearing it's state and then replaying all the events from the beginning.
-    fn replay() -> Result<Self, Self::Error>;
+    // Name of the projection, under which its checkpoint is saved.
+    fn name() -> String;
+    // Events fed to the projection, every event by default.
+    fn filter() -> EventFilter {
+        EventFilter::default()
+    }
+    // Key of the read model the event applies to, the ID of its aggregate by default.
+    fn key(event_envelope: &EventEnvelope<Self::Event>) -> String {
+        event_envelope.aggregate_id.clone()
+    }
+    // Apply event to projection, returning `None` when the event removes the read model.  Must be
+    // idempotent unless the read model repository saves the checkpoint in the same transaction as
+    // the read model, since an event may otherwise be applied again after a restart.
+    fn apply(state: Option<Self>, event: Self::Event) -> Result<Option<Self>, Self::Error>;
 }
@@ -0,0 +1,18 @@
+pub mod file;
+pub mod in_memory;
+
+use crate::Error;
+
+/// Durable position of the subscribers of an event store, so that they can resume where they left
+/// off whatever delivered the events to them.
+///
+/// The checkpoint of a subscriber is the global position of the last event it has processed.
+#[async_trait::async_trait]
+pub trait CheckpointStore: Sized + Send + Sync + Clone {
+    // Fetch the checkpoint of the subscriber, or `None` when it has not saved one yet.
+    async fn load(&self, subscriber: &str) -> Result<Option<i64>, Error>;
+    // Save the checkpoint of the subscriber, replacing its previous one.
+    async fn save(&self, subscriber: &str, position: i64) -> Result<(), Error>;
+    // Forget the checkpoint of the subscriber, e.g. before its read model is rebuilt.
+    async fn delete(&self, subscriber: &str) -> Result<(), Error>;
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::projection::checkpoint::CheckpointStore;
+use crate::Error;
+
+/// Checkpoint store that keeps the checkpoints of every subscriber in a JSON file.
+///
+/// The file is replaced as a whole on every save, by writing a temporary file next to it, flushing
+/// it to disk and then renaming it, so that a crash never leaves a partially written file behind.
+/// On Unix the directory is flushed as well so that the rename survives a crash.  Clones share a
+/// lock so that saves from the same process do not overwrite each other; the file must not be shared
+/// with other processes.  The file is accessed with `tokio::fs`, so the store has to be used from
+/// within a Tokio runtime.
+///
+/// # Example
+///
+/// ```
+/// # use event_sourcing::projection::checkpoint::CheckpointStore;
+/// # use event_sourcing::projection::checkpoint::file::FileCheckpointStore;
+///
+/// # tokio::runtime::Runtime::new().expect("expected runtime").block_on(async {
+/// # let path = std::env::temp_dir().join(format!("checkpoints-{}.json", uuid::Uuid::new_v4()));
+/// let checkpoint_store = FileCheckpointStore::new(&path);
+/// checkpoint_store
+///     .save("balances", 42)
+///     .await
+///     .expect("expected saved checkpoint");
+/// let position = FileCheckpointStore::new(&path)
+///     .load("balances")
+///     .await
+///     .expect("expected checkpoint");
+///
+/// # assert_eq!(position, Some(42));
+/// # std::fs::remove_file(&path).expect("expected removed file");
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileCheckpointStore {
+    path: PathBuf,
+    // Held while the file is read and replaced.
+    lock: Arc<Mutex<()>>,
+}
+
+impl FileCheckpointStore {
+    /// Keep the checkpoints in the file at the path, which is created on the first save.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Arc::default(),
+        }
+    }
+
+    async fn read(&self) -> Result<HashMap<String, i64>, Error> {
+        match fs::read(&self.path).await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(error) => Err(Error::backend(error)),
+        }
+    }
+
+    async fn write(&self, checkpoints: &HashMap<String, i64>) -> Result<(), Error> {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".tmp");
+        let mut file = fs::File::create(&path).await.map_err(Error::backend)?;
+        file.write_all(&serde_json::to_vec(checkpoints)?)
+            .await
+            .map_err(Error::backend)?;
+        file.sync_all().await.map_err(Error::backend)?;
+        fs::rename(&path, &self.path)
+            .await
+            .map_err(Error::backend)?;
+        self.sync_directory().await
+    }
+
+    // Flush the directory holding the file so that its latest rename is durable.
+    #[cfg(unix)]
+    async fn sync_directory(&self) -> Result<(), Error> {
+        let directory = match self.path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => std::path::Path::new("."),
+        };
+        fs::File::open(directory)
+            .await
+            .map_err(Error::backend)?
+            .sync_all()
+            .await
+            .map_err(Error::backend)
+    }
+
+    // Directories cannot be opened to be flushed on other platforms.
+    #[cfg(not(unix))]
+    async fn sync_directory(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // Read the checkpoints, let `update` change them and write them back while holding the lock.
+    async fn update(&self, update: impl FnOnce(&mut HashMap<String, i64>)) -> Result<(), Error> {
+        let _lock = self.lock.lock().await;
+        let mut checkpoints = self.read().await?;
+        update(&mut checkpoints);
+        self.write(&checkpoints).await
+    }
+}
+
+#[async_trait::async_trait]
+impl CheckpointStore for FileCheckpointStore {
+    async fn load(&self, subscriber: &str) -> Result<Option<i64>, Error> {
+        let _lock = self.lock.lock().await;
+        Ok(self.read().await?.get(subscriber).copied())
+    }
+
+    async fn save(&self, subscriber: &str, position: i64) -> Result<(), Error> {
+        self.update(|checkpoints| {
+            checkpoints.insert(String::from(subscriber), position);
+        })
+        .await
+    }
+
+    async fn delete(&self, subscriber: &str) -> Result<(), Error> {
+        self.update(|checkpoints| {
+            checkpoints.remove(subscriber);
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn it_keeps_checkpoints_across_instances() {
+        let path = std::env::temp_dir().join(format!("checkpoints-{}.json", Uuid::new_v4()));
+        let checkpoint_store = FileCheckpointStore::new(&path);
+        assert_eq!(
+            checkpoint_store
+                .load("balances")
+                .await
+                .expect("expected checkpoint"),
+            None
+        );
+        checkpoint_store
+            .save("balances", 1)
+            .await
+            .expect("expected saved checkpoint");
+        checkpoint_store
+            .save("statements", 2)
+            .await
+            .expect("expected saved checkpoint");
+        checkpoint_store
+            .delete("balances")
+            .await
+            .expect("expected deleted checkpoint");
+
+        let checkpoint_store = FileCheckpointStore::new(&path);
+        assert_eq!(
+            checkpoint_store
+                .load("balances")
+                .await
+                .expect("expected checkpoint"),
+            None
+        );
+        assert_eq!(
+            checkpoint_store
+                .load("statements")
+                .await
+                .expect("expected checkpoint"),
+            Some(2)
+        );
+        std::fs::remove_file(&path).expect("expected removed file");
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::projection::checkpoint::CheckpointStore;
+use crate::Error;
+
+/// Checkpoint store that keeps every checkpoint in memory, intended for tests and local development.
+///
+/// Clones share the same underlying state, so a single store can be handed to many tasks.
+///
+/// # Example
+///
+/// ```
+/// # use event_sourcing::projection::checkpoint::CheckpointStore;
+/// # use event_sourcing::projection::checkpoint::in_memory::InMemoryCheckpointStore;
+///
+/// # futures::executor::block_on(async {
+/// let checkpoint_store = InMemoryCheckpointStore::default();
+/// checkpoint_store
+///     .save("balances", 42)
+///     .await
+///     .expect("expected saved checkpoint");
+/// let position = checkpoint_store
+///     .load("balances")
+///     .await
+///     .expect("expected checkpoint");
+///
+/// # assert_eq!(position, Some(42));
+/// # });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryCheckpointStore {
+    checkpoints: Arc<RwLock<HashMap<String, i64>>>,
+}
+
+#[async_trait::async_trait]
+impl CheckpointStore for InMemoryCheckpointStore {
+    async fn load(&self, subscriber: &str) -> Result<Option<i64>, Error> {
+        let checkpoints = self
+            .checkpoints
+            .read()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        Ok(checkpoints.get(subscriber).copied())
+    }
+
+    async fn save(&self, subscriber: &str, position: i64) -> Result<(), Error> {
+        let mut checkpoints = self
+            .checkpoints
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        checkpoints.insert(String::from(subscriber), position);
+        Ok(())
+    }
+
+    async fn delete(&self, subscriber: &str) -> Result<(), Error> {
+        let mut checkpoints = self
+            .checkpoints
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        checkpoints.remove(subscriber);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_saves_and_deletes_checkpoints_per_subscriber() {
+        let checkpoint_store = InMemoryCheckpointStore::default();
+        checkpoint_store
+            .save("balances", 1)
+            .await
+            .expect("expected saved checkpoint");
+        checkpoint_store
+            .save("balances", 2)
+            .await
+            .expect("expected saved checkpoint");
+        checkpoint_store
+            .save("statements", 3)
+            .await
+            .expect("expected saved checkpoint");
+        assert_eq!(
+            checkpoint_store
+                .load("balances")
+                .await
+                .expect("expected checkpoint"),
+            Some(2)
+        );
+        checkpoint_store
+            .delete("balances")
+            .await
+            .expect("expected deleted checkpoint");
+        assert_eq!(
+            checkpoint_store
+                .load("balances")
+                .await
+                .expect("expected checkpoint"),
+            None
+        );
+        assert_eq!(
+            checkpoint_store
+                .load("statements")
+                .await
+                .expect("expected checkpoint"),
+            Some(3)
+        );
+    }
+}
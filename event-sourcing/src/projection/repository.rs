@@ -0,0 +1,38 @@
+pub mod in_memory;
+
+use crate::projection::checkpoint::CheckpointStore;
+use crate::Error;
+
+/// Storage of the read models built by a projection, keyed by the projection.
+#[async_trait::async_trait]
+pub trait ReadModelRepository<Model>: Sized + Send + Sync + Clone
+where
+    Model: Send + Sync + Clone + 'static,
+{
+    // Fetch the read model stored under the key, or `None` when there is none.
+    async fn get(&self, key: &str) -> Result<Option<Model>, Error>;
+    // Store the read model under the key, replacing any read model stored under it.
+    async fn upsert(&self, key: &str, model: Model) -> Result<(), Error>;
+    // Remove the read model stored under the key.
+    async fn delete(&self, key: &str) -> Result<(), Error>;
+    // Remove every read model, e.g. before the projection is rebuilt.
+    async fn clear(&self) -> Result<(), Error>;
+    // Store the read model under the key, or remove it when `None`, and save the checkpoint of the
+    // subscriber.  Repositories that can write the checkpoint in the same transaction as the read
+    // model override this.  By default the read model is written first and the checkpoint after, so
+    // an event may be applied twice to a read model when the subscriber stops in between.
+    async fn save_with_checkpoint<C: CheckpointStore>(
+        &self,
+        key: &str,
+        model: Option<Model>,
+        checkpoint_store: &C,
+        subscriber: &str,
+        position: i64,
+    ) -> Result<(), Error> {
+        match model {
+            Some(model) => self.upsert(key, model).await?,
+            None => self.delete(key).await?,
+        }
+        checkpoint_store.save(subscriber, position).await
+    }
+}
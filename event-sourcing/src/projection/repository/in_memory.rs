@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::projection::repository::ReadModelRepository;
+use crate::Error;
+
+/// Read model repository that keeps every read model in memory, intended for tests and local
+/// development.
+///
+/// Clones share the same underlying state, so a single repository can be handed to many tasks.
+///
+/// # Example
+///
+/// ```
+/// # use event_sourcing::projection::repository::ReadModelRepository;
+/// # use event_sourcing::projection::repository::in_memory::InMemoryReadModelRepository;
+///
+/// # futures::executor::block_on(async {
+/// let repository = InMemoryReadModelRepository::default();
+/// repository
+///     .upsert("aggregate_id", 42)
+///     .await
+///     .expect("expected stored read model");
+/// let model = repository
+///     .get("aggregate_id")
+///     .await
+///     .expect("expected read model");
+///
+/// # assert_eq!(model, Some(42));
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct InMemoryReadModelRepository<Model> {
+    models: Arc<RwLock<HashMap<String, Model>>>,
+}
+
+impl<Model> Default for InMemoryReadModelRepository<Model> {
+    fn default() -> Self {
+        Self {
+            models: Arc::default(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<Model> ReadModelRepository<Model> for InMemoryReadModelRepository<Model>
+where
+    Model: Send + Sync + Clone + 'static,
+{
+    async fn get(&self, key: &str) -> Result<Option<Model>, Error> {
+        let models = self
+            .models
+            .read()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        Ok(models.get(key).cloned())
+    }
+
+    async fn upsert(&self, key: &str, model: Model) -> Result<(), Error> {
+        let mut models = self
+            .models
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        models.insert(String::from(key), model);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        let mut models = self
+            .models
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        models.remove(key);
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<(), Error> {
+        let mut models = self
+            .models
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        models.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_upserts_deletes_and_clears_read_models() {
+        let repository = InMemoryReadModelRepository::default();
+        repository
+            .upsert("first", 1)
+            .await
+            .expect("expected stored read model");
+        repository
+            .upsert("first", 2)
+            .await
+            .expect("expected stored read model");
+        repository
+            .upsert("second", 3)
+            .await
+            .expect("expected stored read model");
+        assert_eq!(
+            repository.get("first").await.expect("expected read model"),
+            Some(2)
+        );
+        repository
+            .delete("first")
+            .await
+            .expect("expected deleted read model");
+        assert_eq!(
+            repository.get("first").await.expect("expected read model"),
+            None
+        );
+        repository
+            .clear()
+            .await
+            .expect("expected cleared read models");
+        assert_eq!(
+            repository.get("second").await.expect("expected read model"),
+            None
+        );
+    }
+}
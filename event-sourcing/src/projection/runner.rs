@@ -0,0 +1,540 @@
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use futures::TryStreamExt;
+
+use crate::event::envelope::EventEnvelope;
+use crate::event::store::subscription::CatchUpSubscription;
+use crate::event::store::{EventBatch, EventStore};
+use crate::projection::checkpoint::CheckpointStore;
+use crate::projection::repository::ReadModelRepository;
+use crate::projection::Projection;
+use crate::runtime::Sleep;
+use crate::Error;
+
+/// Feeds the events of an event store into a projection, saving its read models and checkpoint after
+/// every event.
+///
+/// Every event is applied to the read model stored under the key the projection gives it, which is
+/// then stored back in the repository, or removed from it when the projection drops it, along with
+/// the checkpoint.  Unless the repository saves both in a single transaction, the checkpoint is saved
+/// after the read model, so an event may be applied again when the runner stops in between and
+/// `Projection::apply` must be idempotent.  Runners resume after their checkpoint, and a rebuild
+/// starts over from the first event with an empty repository.
+///
+/// # Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use uuid::Uuid;
+/// # use serde::{Deserialize, Serialize};
+/// # use event_sourcing::Error;
+/// # use event_sourcing::event::envelope::EventEnvelope;
+/// # use event_sourcing::event::store::{EventStore, ExpectedVersion};
+/// # use event_sourcing::event::store::in_memory::InMemoryEventStore;
+/// # use event_sourcing::event::EventType;
+/// # use event_sourcing::projection::Projection;
+/// # use event_sourcing::projection::checkpoint::in_memory::InMemoryCheckpointStore;
+/// # use event_sourcing::projection::repository::in_memory::InMemoryReadModelRepository;
+/// # use event_sourcing::projection::runner::ProjectionRunner;
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     id: Uuid,
+/// #     amount: i64,
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// # }
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize)]
+/// # struct TestBalance {
+/// #     total: i64,
+/// # }
+///
+/// # impl Projection for TestBalance {
+/// #     type Event = TestEvent;
+/// #     type Error = Error;
+/// #
+/// #     fn name() -> String {
+/// #         String::from("TestBalance")
+/// #     }
+/// #
+/// #     fn apply(state: Option<Self>, event: Self::Event) -> Result<Option<Self>, Self::Error> {
+/// #         let total = state.map_or(0, |state| state.total);
+/// #         Ok(Some(Self { total: total + event.amount }))
+/// #     }
+/// # }
+///
+/// # futures::executor::block_on(async {
+/// # let test_event = TestEvent {
+/// #     id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
+/// #     amount: 1,
+/// #     description: String::from("Deposit"),
+/// # };
+/// # let event_store = InMemoryEventStore::default();
+/// # event_store
+/// #     .append(
+/// #         &String::from("aggregate_id"),
+/// #         vec![EventEnvelope::new(
+/// #             String::from("aggregate_id"),
+/// #             String::from("TestAggregate"),
+/// #             test_event.clone(),
+/// #             test_event.event_type(),
+/// #             1,
+/// #         )],
+/// #         ExpectedVersion::NoStream,
+/// #     )
+/// #     .await
+/// #     .expect("expected appended events");
+/// let runner: ProjectionRunner<TestBalance, _, _, _> = ProjectionRunner::new(
+///     event_store,
+///     InMemoryReadModelRepository::default(),
+///     InMemoryCheckpointStore::default(),
+/// );
+/// runner.catch_up().await.expect("expected applied events");
+/// let state = runner.state("aggregate_id").await.expect("expected state");
+///
+/// # assert_eq!(state.expect("expected state").total, 1);
+/// # assert_eq!(runner.checkpoint().await.expect("expected checkpoint"), Some(1));
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProjectionRunner<P, E, R, C> {
+    event_store: E,
+    repository: R,
+    checkpoint_store: C,
+    // Number of events read at a time.
+    batch_size: usize,
+    // Interval at which stores that cannot notify are polled by `run` once caught up.
+    polling: Option<(Duration, Sleep)>,
+    projection: PhantomData<P>,
+}
+
+impl<P, E, R, C> ProjectionRunner<P, E, R, C>
+where
+    P: Projection + 'static,
+    E: EventStore + 'static,
+    R: ReadModelRepository<P>,
+    C: CheckpointStore,
+    Error: From<P::Error>,
+{
+    pub fn new(event_store: E, repository: R, checkpoint_store: C) -> Self {
+        Self {
+            event_store,
+            repository,
+            checkpoint_store,
+            batch_size: 500,
+            polling: None,
+            projection: PhantomData,
+        }
+    }
+
+    /// Read events `batch_size` at a time.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Poll the store every interval in `run` when it cannot notify the runner of new events.
+    pub fn with_polling(mut self, interval: Duration, sleep: Sleep) -> Self {
+        self.polling = Some((interval, sleep));
+        self
+    }
+
+    /// Current read model stored under the key.
+    pub async fn state(&self, key: &str) -> Result<Option<P>, Error> {
+        self.repository.get(key).await
+    }
+
+    /// Global position of the last event applied to the projection.
+    pub async fn checkpoint(&self) -> Result<Option<i64>, Error> {
+        self.checkpoint_store.load(&P::name()).await
+    }
+
+    /// Apply the event to the read model stored under its key, then save or delete the read model
+    /// along with the checkpoint.
+    pub async fn handle(&self, event_envelope: EventEnvelope<P::Event>) -> Result<(), Error> {
+        let key = P::key(&event_envelope);
+        let position = event_envelope.position;
+        let state = self.repository.get(&key).await?;
+        let state = P::apply(state, event_envelope.data)?;
+        self.repository
+            .save_with_checkpoint(&key, state, &self.checkpoint_store, &P::name(), position)
+            .await
+    }
+
+    /// Apply every event stored after the checkpoint and return how many were applied.
+    pub async fn catch_up(&self) -> Result<usize, Error> {
+        let mut position = self.next_position().await?;
+        let mut applied = 0;
+        loop {
+            let event_batch: EventBatch<P::Event> = self
+                .event_store
+                .read_all(position, self.batch_size, &P::filter())
+                .await?;
+            if event_batch.event_envelopes.is_empty() && event_batch.next_position <= position {
+                return Ok(applied);
+            }
+            // Positions start at 1, so there is nothing to save before the first one.
+            let mut checkpoint = (position - 1).max(0);
+            position = event_batch.next_position;
+            for event_envelope in event_batch.event_envelopes {
+                checkpoint = event_envelope.position;
+                self.handle(event_envelope).await?;
+                applied += 1;
+            }
+            // Move the checkpoint past the events the filter skipped, so they are not scanned again.
+            if checkpoint < position - 1 {
+                self.checkpoint_store.save(&P::name(), position - 1).await?;
+            }
+        }
+    }
+
+    /// Apply every event stored after the checkpoint, then keep applying new events as they are
+    /// stored.  Only returns when an event cannot be applied, or once caught up when the store can
+    /// neither notify the runner nor be polled.
+    pub async fn run(&self) -> Result<(), Error> {
+        let mut subscription =
+            CatchUpSubscription::new(self.event_store.clone(), self.next_position().await?)
+                .with_filter(P::filter())
+                .with_batch_size(self.batch_size);
+        if let Some((interval, sleep)) = self.polling {
+            subscription = subscription.with_polling(interval, sleep);
+        }
+        let mut event_envelopes = subscription.stream::<P::Event>();
+        while let Some(event_envelope) = event_envelopes.try_next().await? {
+            self.handle(event_envelope).await?;
+        }
+        Ok(())
+    }
+
+    /// Drop every read model and the checkpoint of the projection.
+    pub async fn reset(&self) -> Result<(), Error> {
+        self.repository.clear().await?;
+        self.checkpoint_store.delete(&P::name()).await
+    }
+
+    /// Reset the projection and replay every stored event into it from the first position, then
+    /// return how many were applied.
+    pub async fn rebuild(&self) -> Result<usize, Error> {
+        self.reset().await?;
+        self.catch_up().await
+    }
+
+    // Global position of the first event that has not been applied to the projection yet.
+    async fn next_position(&self) -> Result<i64, Error> {
+        Ok(self.checkpoint().await?.map_or(0, |position| position + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::event::store::in_memory::InMemoryEventStore;
+    use crate::event::store::EventFilter;
+    use crate::fixtures::{append, TestEvent};
+    use crate::projection::checkpoint::in_memory::InMemoryCheckpointStore;
+    use crate::projection::repository::in_memory::InMemoryReadModelRepository;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct TestBalance {
+        total: i64,
+        events: i64,
+    }
+
+    impl Projection for TestBalance {
+        type Event = TestEvent;
+        type Error = Error;
+
+        fn name() -> String {
+            String::from("TestBalance")
+        }
+
+        fn filter() -> EventFilter {
+            EventFilter::default().with_aggregate_type("TestAggregate")
+        }
+
+        fn apply(state: Option<Self>, event: Self::Event) -> Result<Option<Self>, Self::Error> {
+            let state = state.unwrap_or(Self {
+                total: 0,
+                events: 0,
+            });
+            // Balances are removed once they are closed by an event of amount zero.
+            if event.amount == 0 {
+                return Ok(None);
+            }
+            Ok(Some(Self {
+                total: state.total + event.amount,
+                events: state.events + 1,
+            }))
+        }
+    }
+
+    type TestRunner = ProjectionRunner<
+        TestBalance,
+        InMemoryEventStore,
+        InMemoryReadModelRepository<TestBalance>,
+        InMemoryCheckpointStore,
+    >;
+
+    async fn total(runner: &TestRunner, key: &str) -> (i64, i64) {
+        let state = runner
+            .state(key)
+            .await
+            .expect("expected no error")
+            .expect("expected state");
+        (state.total, state.events)
+    }
+
+    #[tokio::test]
+    async fn it_applies_events_to_read_model_of_their_key() {
+        let event_store = InMemoryEventStore::default();
+        append(&event_store, "TestAggregate", "first", vec![1, 2]).await;
+        append(&event_store, "TestAggregate", "second", vec![3]).await;
+        append(&event_store, "OtherAggregate", "first", vec![100]).await;
+        let runner: TestRunner = ProjectionRunner::new(
+            event_store,
+            InMemoryReadModelRepository::default(),
+            InMemoryCheckpointStore::default(),
+        );
+        assert_eq!(runner.catch_up().await.expect("expected applied events"), 3);
+        assert_eq!(total(&runner, "first").await, (3, 2));
+        assert_eq!(total(&runner, "second").await, (3, 1));
+    }
+
+    #[tokio::test]
+    async fn it_deletes_read_model_dropped_by_projection() {
+        let event_store = InMemoryEventStore::default();
+        append(&event_store, "TestAggregate", "first", vec![1, 2, 0]).await;
+        append(&event_store, "TestAggregate", "second", vec![3]).await;
+        let runner: TestRunner = ProjectionRunner::new(
+            event_store,
+            InMemoryReadModelRepository::default(),
+            InMemoryCheckpointStore::default(),
+        );
+        assert_eq!(runner.catch_up().await.expect("expected applied events"), 4);
+        assert!(runner
+            .state("first")
+            .await
+            .expect("expected no error")
+            .is_none());
+        assert_eq!(total(&runner, "second").await, (3, 1));
+    }
+
+    #[tokio::test]
+    async fn it_resumes_after_checkpoint() {
+        let event_store = InMemoryEventStore::default();
+        let repository = InMemoryReadModelRepository::default();
+        let checkpoint_store = InMemoryCheckpointStore::default();
+        append(&event_store, "TestAggregate", "first", vec![1, 2]).await;
+        append(&event_store, "OtherAggregate", "other", vec![100]).await;
+        let runner: TestRunner = ProjectionRunner::new(
+            event_store.clone(),
+            repository.clone(),
+            checkpoint_store.clone(),
+        )
+        .with_batch_size(1);
+        assert_eq!(runner.catch_up().await.expect("expected applied events"), 2);
+        assert_eq!(total(&runner, "first").await, (3, 2));
+        // The checkpoint moves past the event of the other aggregate as well.
+        assert_eq!(
+            runner.checkpoint().await.expect("expected checkpoint"),
+            Some(3)
+        );
+
+        append(&event_store, "TestAggregate", "first", vec![3]).await;
+        let runner: TestRunner = ProjectionRunner::new(event_store, repository, checkpoint_store);
+        assert_eq!(runner.catch_up().await.expect("expected applied events"), 1);
+        assert_eq!(total(&runner, "first").await, (6, 3));
+        assert_eq!(
+            runner.checkpoint().await.expect("expected checkpoint"),
+            Some(4)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_saves_checkpoint_past_skipped_events() {
+        let event_store = InMemoryEventStore::default();
+        append(&event_store, "TestAggregate", "first", vec![1]).await;
+        append(&event_store, "OtherAggregate", "other", vec![100, 100, 100]).await;
+        let runner: TestRunner = ProjectionRunner::new(
+            event_store,
+            InMemoryReadModelRepository::default(),
+            InMemoryCheckpointStore::default(),
+        );
+        assert_eq!(runner.catch_up().await.expect("expected applied events"), 1);
+        assert_eq!(
+            runner.checkpoint().await.expect("expected checkpoint"),
+            Some(4)
+        );
+        assert_eq!(runner.catch_up().await.expect("expected applied events"), 0);
+    }
+
+    #[tokio::test]
+    async fn it_applies_live_events() {
+        let event_store = InMemoryEventStore::default();
+        append(&event_store, "TestAggregate", "first", vec![1]).await;
+        let runner: TestRunner = ProjectionRunner::new(
+            event_store.clone(),
+            InMemoryReadModelRepository::default(),
+            InMemoryCheckpointStore::default(),
+        );
+        let running = runner.clone();
+        let mut run = tokio::spawn(async move { running.run().await });
+        append(&event_store, "TestAggregate", "first", vec![2]).await;
+        let applied = async {
+            while runner.checkpoint().await.expect("expected checkpoint") != Some(2) {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::select! {
+            result = &mut run => panic!("expected running projection, got {result:?}"),
+            result = tokio::time::timeout(Duration::from_secs(5), applied) => {
+                result.expect("expected live event to be applied");
+            }
+        }
+        run.abort();
+        assert!(run
+            .await
+            .expect_err("expected aborted projection")
+            .is_cancelled());
+        assert_eq!(total(&runner, "first").await, (3, 2));
+    }
+
+    #[tokio::test]
+    async fn it_rebuilds_from_first_event() {
+        let event_store = InMemoryEventStore::default();
+        let repository = InMemoryReadModelRepository::default();
+        append(&event_store, "TestAggregate", "first", vec![1, 2, 3]).await;
+        let runner: TestRunner = ProjectionRunner::new(
+            event_store,
+            repository.clone(),
+            InMemoryCheckpointStore::default(),
+        );
+        runner.catch_up().await.expect("expected applied events");
+        // Read models that drifted from the events are replaced or dropped by the rebuild.
+        repository
+            .upsert(
+                "first",
+                TestBalance {
+                    total: 100,
+                    events: 100,
+                },
+            )
+            .await
+            .expect("expected stored read model");
+        repository
+            .upsert(
+                "stale",
+                TestBalance {
+                    total: 100,
+                    events: 100,
+                },
+            )
+            .await
+            .expect("expected stored read model");
+
+        assert_eq!(runner.rebuild().await.expect("expected rebuilt state"), 3);
+        assert_eq!(total(&runner, "first").await, (6, 3));
+        assert!(runner
+            .state("stale")
+            .await
+            .expect("expected no error")
+            .is_none());
+        assert_eq!(
+            runner.checkpoint().await.expect("expected checkpoint"),
+            Some(3)
+        );
+    }
+
+    // Repository that saves checkpoints in the same transaction as the read models, as a database
+    // backed repository would.
+    #[derive(Debug, Clone, Default)]
+    struct TransactionalRepository {
+        models: InMemoryReadModelRepository<TestBalance>,
+        checkpoints: InMemoryCheckpointStore,
+        transactions: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl ReadModelRepository<TestBalance> for TransactionalRepository {
+        async fn get(&self, key: &str) -> Result<Option<TestBalance>, Error> {
+            self.models.get(key).await
+        }
+
+        async fn upsert(&self, key: &str, model: TestBalance) -> Result<(), Error> {
+            self.models.upsert(key, model).await
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), Error> {
+            self.models.delete(key).await
+        }
+
+        async fn clear(&self) -> Result<(), Error> {
+            self.models.clear().await
+        }
+
+        async fn save_with_checkpoint<C: CheckpointStore>(
+            &self,
+            key: &str,
+            model: Option<TestBalance>,
+            _checkpoint_store: &C,
+            subscriber: &str,
+            position: i64,
+        ) -> Result<(), Error> {
+            match model {
+                Some(model) => self.models.upsert(key, model).await?,
+                None => self.models.delete(key).await?,
+            }
+            self.checkpoints.save(subscriber, position).await?;
+            self.transactions.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl CheckpointStore for TransactionalRepository {
+        async fn load(&self, subscriber: &str) -> Result<Option<i64>, Error> {
+            self.checkpoints.load(subscriber).await
+        }
+
+        async fn save(&self, subscriber: &str, position: i64) -> Result<(), Error> {
+            self.checkpoints.save(subscriber, position).await
+        }
+
+        async fn delete(&self, subscriber: &str) -> Result<(), Error> {
+            self.checkpoints.delete(subscriber).await
+        }
+    }
+
+    #[tokio::test]
+    async fn it_saves_checkpoint_with_read_model_in_one_transaction() {
+        let event_store = InMemoryEventStore::default();
+        let repository = TransactionalRepository::default();
+        append(&event_store, "TestAggregate", "first", vec![1, 2]).await;
+        let runner: ProjectionRunner<TestBalance, _, _, _> =
+            ProjectionRunner::new(event_store, repository.clone(), repository.clone());
+        assert_eq!(runner.catch_up().await.expect("expected applied events"), 2);
+        assert_eq!(repository.transactions.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            runner.checkpoint().await.expect("expected checkpoint"),
+            Some(2)
+        );
+        let state = runner
+            .state("first")
+            .await
+            .expect("expected no error")
+            .expect("expected state");
+        assert_eq!((state.total, state.events), (3, 2));
+    }
+}
@@ -0,0 +1,7 @@
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+
+/// Waits for the given duration on the runtime of the application, e.g.
+/// `|duration| Box::pin(tokio::time::sleep(duration))`.
+pub type Sleep = fn(Duration) -> BoxFuture<'static, ()>;
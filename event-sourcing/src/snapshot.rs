@@ -1,2 +1,4 @@
 pub mod envelope;
+pub mod migrator;
+pub mod policy;
 pub mod store;
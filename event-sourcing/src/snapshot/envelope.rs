@@ -3,30 +3,73 @@ use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::aggregate::Aggregate as AggregateType;
+use crate::codec::Codec;
+use crate::snapshot::migrator::SnapshotMigratorRegistry;
 use crate::Error;
 
 /// Event is a domain envelope describing a change that has happened to an aggregate.
-#[derive(Debug, Clone, Serialize, Deserialize, derive_new::new)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SnapshotEnvelope<Aggregate>
-    where
-        Aggregate: Send + Sync + Clone + Serialize,
+where
+    Aggregate: Send + Sync + Clone + Serialize,
 {
     // Unique identifier of the envelope.
-    #[new(value = "Uuid::new_v4()")]
     pub id: Uuid,
     // ID of the aggregate that the envelope belongs to.
     pub aggregate_id: String,
     // Type of the aggregate that the envelope can be applied to.
     pub aggregate_type: String,
+    // Schema version of the aggregate when the snapshot was taken.  Snapshots written before the
+    // schema version was recorded are at version 1.
+    #[serde(default = "default_schema_version")]
+    pub schema_version: i64,
     // Aggregate attached to the envelope.
     pub data: Aggregate,
     // Version of the aggregate after the envelope has been applied.
     pub version: i64,
     // Timestamp of when the envelope was created.
-    #[new(value = "Utc::now()")]
     pub timestamp: DateTime<Utc>,
 }
 
+impl<Aggregate: AggregateType> SnapshotEnvelope<Aggregate> {
+    /// Create a snapshot of the aggregate at the given version, recording the snapshot schema
+    /// version the aggregate declares.
+    pub fn new(
+        aggregate_id: String,
+        aggregate_type: String,
+        data: Aggregate,
+        version: i64,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            aggregate_id,
+            aggregate_type,
+            schema_version: Aggregate::snapshot_schema_version(),
+            data,
+            version,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+// Leading fields of a snapshot envelope, decoded on their own to find out whether the aggregate can
+// be decoded as it is.  They come first so that they can be read from non-self-describing content.
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    #[allow(dead_code)]
+    id: Uuid,
+    #[allow(dead_code)]
+    aggregate_id: String,
+    aggregate_type: String,
+    #[serde(default = "default_schema_version")]
+    schema_version: i64,
+}
+
+fn default_schema_version() -> i64 {
+    1
+}
+
 /// Serialize the Snapshot Envelope struct to a string.
 ///
 /// # Example
@@ -37,8 +80,21 @@ pub struct SnapshotEnvelope<Aggregate>
 /// # use serde::{Deserialize, Serialize};
 /// # use event_sourcing::aggregate::Aggregate;
 /// # use event_sourcing::Error;
+/// # use event_sourcing::event::EventType;
 /// # use event_sourcing::snapshot::envelope::{SnapshotEnvelope, serialize};
 ///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     id: Uuid,
+/// #     amount: i64,
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// # }
 ///
 /// # #[derive(Debug, Clone, Serialize, Deserialize)]
 /// # struct TestAggregate {
@@ -46,6 +102,37 @@ pub struct SnapshotEnvelope<Aggregate>
 /// #     total: i64,
 /// # }
 ///
+/// # impl Aggregate for TestAggregate {
+/// #     type AggregateID = Uuid;
+/// #     type Event = TestEvent;
+/// #     type Error = Error;
+/// #
+/// #     fn aggregate_type() -> String {
+/// #         String::from("TestAggregate")
+/// #     }
+/// #
+/// #     fn aggregate_id(&self) -> &Self::AggregateID {
+/// #         &self.id
+/// #     }
+/// #
+/// #     fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error> {
+/// #         match state {
+/// #             None => Ok(Self { id: event.id, total: event.amount }),
+/// #             Some(mut state) => {
+/// #                 state.total += event.amount;
+/// #                 Ok(state)
+/// #             }
+/// #         }
+/// #     }
+/// #
+/// #     fn apply_all(events: Vec<Self::Event>) -> Result<Self, Self::Error> {
+/// #         events
+/// #             .into_iter()
+/// #             .try_fold(None, |state, event| Self::apply(state, event).map(Some))?
+/// #             .ok_or_else(|| Error::from("Aggregate must not be None"))
+/// #     }
+/// # }
+///
 /// # let test_aggregate = TestAggregate {
 /// #     id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
 /// #     total: 1,
@@ -98,72 +185,143 @@ pub fn deserialize<Aggregate: Send + Sync + Clone + Serialize + DeserializeOwned
     serde_json::from_str(snapshot_envelope.as_str()).map_err(|error| error.into())
 }
 
-#[cfg(test)]
-mod tests {
-    use std::str::FromStr;
-
-    use crate::aggregate::Aggregate;
-    use crate::event::EventType;
-
-    use super::*;
-
-    #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
-    struct TestEvent {
-        id: Uuid,
-        amount: i64,
-    }
+/// Encode the Snapshot Envelope struct to bytes with the given codec.
+///
+/// # Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use uuid::Uuid;
+/// # use serde::{Deserialize, Serialize};
+/// # use event_sourcing::Error;
+/// # use event_sourcing::aggregate::Aggregate;
+/// # use event_sourcing::codec::JsonCodec;
+/// # use event_sourcing::event::EventType;
+/// # use event_sourcing::snapshot::envelope::{decode, encode, SnapshotEnvelope};
+/// # use event_sourcing::snapshot::migrator::SnapshotMigratorRegistry;
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     id: Uuid,
+/// #     amount: i64,
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// # }
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize)]
+/// # struct TestAggregate {
+/// #     id: Uuid,
+/// #     total: i64,
+/// # }
+///
+/// # impl Aggregate for TestAggregate {
+/// #     type AggregateID = Uuid;
+/// #     type Event = TestEvent;
+/// #     type Error = Error;
+/// #
+/// #     fn aggregate_type() -> String {
+/// #         String::from("TestAggregate")
+/// #     }
+/// #
+/// #     fn aggregate_id(&self) -> &Self::AggregateID {
+/// #         &self.id
+/// #     }
+/// #
+/// #     fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error> {
+/// #         match state {
+/// #             None => Ok(Self { id: event.id, total: event.amount }),
+/// #             Some(mut state) => {
+/// #                 state.total += event.amount;
+/// #                 Ok(state)
+/// #             }
+/// #         }
+/// #     }
+/// #
+/// #     fn apply_all(events: Vec<Self::Event>) -> Result<Self, Self::Error> {
+/// #         events
+/// #             .into_iter()
+/// #             .try_fold(None, |state, event| Self::apply(state, event).map(Some))?
+/// #             .ok_or_else(|| Error::from("Aggregate must not be None"))
+/// #     }
+/// # }
+///
+/// # let test_aggregate = TestAggregate {
+/// #     id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
+/// #     total: 1,
+/// # };
+/// # let snapshot_envelope: SnapshotEnvelope<TestAggregate> = SnapshotEnvelope::new(
+/// #     String::from("aggregate_id"),
+/// #     String::from("TestAggregate"),
+/// #     test_aggregate,
+/// #     1,
+/// # );
+/// let bytes = encode(&snapshot_envelope, &JsonCodec).expect("expected encoded struct");
+/// let decoded_snapshot_envelope: Option<SnapshotEnvelope<TestAggregate>> =
+///     decode(&JsonCodec, &bytes, &SnapshotMigratorRegistry::default())
+///         .expect("expected decoded struct");
+///
+/// # let decoded_snapshot_envelope = decoded_snapshot_envelope.expect("expected snapshot");
+/// # assert_eq!(decoded_snapshot_envelope.id, snapshot_envelope.id);
+/// # assert_eq!(decoded_snapshot_envelope.schema_version, 1);
+/// # assert_eq!(decoded_snapshot_envelope.data.total, 1);
+/// ```
+pub fn encode<Aggregate: Send + Sync + Clone + Serialize + DeserializeOwned, C: Codec>(
+    snapshot_envelope: &SnapshotEnvelope<Aggregate>,
+    codec: &C,
+) -> Result<Vec<u8>, Error> {
+    codec.encode(snapshot_envelope)
+}
 
-    impl EventType for TestEvent {
-        fn event_type(&self) -> String {
-            String::from("TestEvent")
-        }
+/// Decode bytes encoded with the given codec to a Snapshot Envelope struct.
+///
+/// Returns `None` when the snapshot was taken at another schema version than the one the aggregate
+/// declares and the migrators cannot bring it up to date, in which case the aggregate should be
+/// rebuilt from its events.  Only self-describing content can be migrated.
+pub fn decode<Aggregate: AggregateType, C: Codec>(
+    codec: &C,
+    snapshot_envelope: &[u8],
+    migrators: &SnapshotMigratorRegistry,
+) -> Result<Option<SnapshotEnvelope<Aggregate>>, Error> {
+    let header: SnapshotHeader = codec.decode(snapshot_envelope)?;
+    let schema_version = Aggregate::snapshot_schema_version();
+    if header.schema_version == schema_version {
+        return codec.decode(snapshot_envelope).map(Some);
     }
-
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    struct TestAggregate {
-        id: Uuid,
-        total: i64,
+    if header.schema_version > schema_version || !codec.is_self_describing() {
+        return Ok(None);
     }
-
-    impl Aggregate for TestAggregate {
-        type AggregateID = Uuid;
-        type Event = TestEvent;
-        type Error = Error;
-
-        fn aggregate_id(&self) -> &Self::AggregateID {
-            return &self.id;
+    let mut snapshot_envelope: serde_json::Value = codec.decode(snapshot_envelope)?;
+    match migrators.migrate(
+        &header.aggregate_type,
+        header.schema_version,
+        schema_version,
+        snapshot_envelope["data"].take(),
+    )? {
+        Some(data) => {
+            snapshot_envelope["schema_version"] = serde_json::Value::from(schema_version);
+            snapshot_envelope["data"] = data;
+            Ok(Some(serde_json::from_value(snapshot_envelope)?))
         }
+        None => Ok(None),
+    }
+}
 
-        fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error> {
-            match state {
-                None => Ok(Self {
-                    id: event.id,
-                    total: event.amount,
-                }),
-                Some(mut state) => {
-                    state.total = state.total + event.amount;
-                    Ok(state)
-                }
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregate;
+    use crate::codec::ContentType;
+    use crate::fixtures::{test_event, test_id, TestAggregate};
 
-        fn apply_all(events: Vec<Self::Event>) -> Result<Self, Self::Error> {
-            match events.into_iter().fold(Ok(None), |state, event| {
-                Self::apply(state?, event).map(|new_state| Some(new_state))
-            }) {
-                Ok(Some(state)) => Ok(state),
-                Ok(None) => Err(Error::try_from("Aggregate must not be None").unwrap()),
-                Err(error) => Err(error),
-            }
-        }
-    }
+    use super::*;
 
     #[test]
     fn it_serializes_and_deserializes() {
-        let test_event = TestEvent {
-            id: Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid"),
-            amount: 1,
-        };
+        let test_event = test_event(1);
         let test_aggregate =
             TestAggregate::apply_all(vec![test_event]).expect("expected aggregate");
         let event_envelope: SnapshotEnvelope<TestAggregate> = SnapshotEnvelope::new(
@@ -179,10 +337,40 @@ mod tests {
         assert_eq!(event_envelope.aggregate_id, String::from("aggregate_id"));
         assert_eq!(event_envelope.aggregate_type, String::from("TestAggregate"));
         assert_eq!(event_envelope.version, 0);
-        assert_eq!(
-            event_envelope.data.id,
-            Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid")
-        );
+        assert_eq!(event_envelope.data.id, test_id());
         assert_eq!(event_envelope.data.total, 1);
     }
+
+    #[test]
+    fn it_encodes_and_decodes_every_enabled_content_type() {
+        let mut content_types = vec![ContentType::Json];
+        if cfg!(feature = "msgpack") {
+            content_types.push(ContentType::MessagePack);
+        }
+        if cfg!(feature = "cbor") {
+            content_types.push(ContentType::Cbor);
+        }
+        if cfg!(feature = "bincode") {
+            content_types.push(ContentType::Bincode);
+        }
+        for content_type in content_types {
+            let snapshot_envelope: SnapshotEnvelope<TestAggregate> = SnapshotEnvelope::new(
+                String::from("aggregate_id"),
+                TestAggregate::aggregate_type(),
+                TestAggregate {
+                    id: test_id(),
+                    total: 1,
+                },
+                3,
+            );
+            let bytes = encode(&snapshot_envelope, &content_type).expect("expected encoded struct");
+            let decoded_snapshot_envelope: SnapshotEnvelope<TestAggregate> =
+                decode(&content_type, &bytes, &SnapshotMigratorRegistry::default())
+                    .expect("expected decoded struct")
+                    .expect("expected snapshot");
+            assert_eq!(decoded_snapshot_envelope.id, snapshot_envelope.id);
+            assert_eq!(decoded_snapshot_envelope.schema_version, 1);
+            assert_eq!(decoded_snapshot_envelope.data.total, 1);
+        }
+    }
 }
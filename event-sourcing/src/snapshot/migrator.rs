@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::Error;
+
+/// Transform moving the JSON data of a snapshot from one schema version to the next.
+pub type Migrate = fn(data: Value) -> Result<Value, Error>;
+
+/// Registry of transforms that bring snapshots taken at an older schema version up to the current
+/// shape of their aggregate.
+///
+/// Migrators are keyed by aggregate type and the schema version they migrate from.  A snapshot is
+/// only migrated when the migrators can be chained all the way to the schema version the aggregate
+/// declares; otherwise it is ignored and the aggregate is rebuilt from its events.
+///
+/// # Example
+///
+/// ```
+/// # use serde_json::{json, Value};
+/// # use event_sourcing::Error;
+/// # use event_sourcing::snapshot::migrator::SnapshotMigratorRegistry;
+///
+/// fn rename_total(mut data: Value) -> Result<Value, Error> {
+///     let total = data["total"].take();
+///     data["balance"] = total;
+///     Ok(data)
+/// }
+///
+/// let migrators = SnapshotMigratorRegistry::default().register("TestAggregate", 1, rename_total);
+/// let data = migrators
+///     .migrate("TestAggregate", 1, 2, json!({ "total": 1 }))
+///     .expect("expected migrated data");
+///
+/// # assert_eq!(data.expect("expected migrated data")["balance"], 1);
+/// # assert_eq!(migrators.migrate("TestAggregate", 1, 3, json!({})).expect("expected no error"), None);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotMigratorRegistry {
+    migrators: HashMap<(String, i64), Migrate>,
+}
+
+impl SnapshotMigratorRegistry {
+    /// Register a migrator moving snapshots of `aggregate_type` from `schema_version` to
+    /// `schema_version + 1`.
+    pub fn register(
+        mut self,
+        aggregate_type: impl Into<String>,
+        schema_version: i64,
+        migrate: Migrate,
+    ) -> Self {
+        self.migrators
+            .insert((aggregate_type.into(), schema_version), migrate);
+        self
+    }
+
+    /// Migrate the data of a snapshot of the aggregate type from `schema_version` to
+    /// `target_schema_version`.  Returns `None` when a migrator is missing along the way.
+    pub fn migrate(
+        &self,
+        aggregate_type: &str,
+        mut schema_version: i64,
+        target_schema_version: i64,
+        mut data: Value,
+    ) -> Result<Option<Value>, Error> {
+        while schema_version < target_schema_version {
+            match self
+                .migrators
+                .get(&(String::from(aggregate_type), schema_version))
+            {
+                Some(migrate) => data = migrate(data)?,
+                None => return Ok(None),
+            }
+            schema_version += 1;
+        }
+        Ok(Some(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn add_currency(mut data: Value) -> Result<Value, Error> {
+        data["currency"] = Value::from("EUR");
+        Ok(data)
+    }
+
+    fn double_total(mut data: Value) -> Result<Value, Error> {
+        let total = data["total"]
+            .as_i64()
+            .ok_or_else(|| Error::serialization("expected a total"))?;
+        data["total"] = Value::from(total * 2);
+        Ok(data)
+    }
+
+    #[test]
+    fn it_chains_migrators_up_to_target_schema_version() {
+        let migrators = SnapshotMigratorRegistry::default()
+            .register("TestAggregate", 2, add_currency)
+            .register("TestAggregate", 1, double_total);
+        let data = migrators
+            .migrate("TestAggregate", 1, 3, json!({ "total": 1 }))
+            .expect("expected migrated data");
+        assert_eq!(data, Some(json!({ "total": 2, "currency": "EUR" })));
+        let data = migrators
+            .migrate("TestAggregate", 1, 2, json!({ "total": 1 }))
+            .expect("expected migrated data");
+        assert_eq!(data, Some(json!({ "total": 2 })));
+    }
+
+    #[test]
+    fn it_gives_up_when_a_migrator_is_missing() {
+        let migrators =
+            SnapshotMigratorRegistry::default().register("TestAggregate", 2, add_currency);
+        let data = migrators
+            .migrate("TestAggregate", 1, 3, json!({ "total": 1 }))
+            .expect("expected no error");
+        assert_eq!(data, None);
+        let data = migrators
+            .migrate("OtherAggregate", 2, 3, json!({ "total": 1 }))
+            .expect("expected no error");
+        assert_eq!(data, None);
+    }
+}
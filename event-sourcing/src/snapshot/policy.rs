@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+
+use crate::Error;
+
+/// Spawns a future onto the runtime of the application, e.g. `|future| { tokio::spawn(future); }`.
+pub type Spawn = fn(BoxFuture<'static, ()>);
+
+/// Reports that the snapshot of an aggregate could not be taken, given the id of the aggregate and
+/// the error, e.g. to log it.
+pub type SnapshotErrorHandler = fn(&str, &Error);
+
+/// What is known about an aggregate right after new events have been saved for it, used to decide
+/// whether a snapshot should be taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotContext {
+    // Version of the aggregate after the new events.
+    pub version: i64,
+    // Version of the snapshot the aggregate was loaded from, or `0` when it was loaded without one.
+    pub snapshot_version: i64,
+    // Number of events replayed on top of the snapshot when the aggregate was loaded.
+    pub replayed_events: i64,
+    // Time it took to load the aggregate.
+    pub replay_duration: Duration,
+}
+
+impl SnapshotContext {
+    /// Number of events the aggregate holds on top of its latest snapshot.
+    pub fn events_since_snapshot(&self) -> i64 {
+        self.version - self.snapshot_version
+    }
+}
+
+/// Decides when a snapshot of an aggregate is taken after new events have been saved.
+///
+/// # Example
+///
+/// ```
+/// # use std::time::Duration;
+/// # use event_sourcing::snapshot::policy::{SnapshotContext, SnapshotPolicy};
+///
+/// let context = SnapshotContext {
+///     version: 120,
+///     snapshot_version: 100,
+///     replayed_events: 19,
+///     replay_duration: Duration::from_millis(5),
+/// };
+///
+/// # assert!(SnapshotPolicy::EveryNEvents(20).should_snapshot(&context));
+/// # assert!(!SnapshotPolicy::EveryNEvents(50).should_snapshot(&context));
+/// # assert!(!SnapshotPolicy::ReplayDuration(Duration::from_millis(10)).should_snapshot(&context));
+/// # assert!(SnapshotPolicy::Custom(|context| context.version % 10 == 0).should_snapshot(&context));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub enum SnapshotPolicy {
+    // Snapshot once the aggregate holds at least this many events on top of its latest snapshot.
+    EveryNEvents(i64),
+    // Snapshot when loading the aggregate took at least this long.
+    ReplayDuration(Duration),
+    // Snapshot when the predicate returns `true`.
+    Custom(fn(&SnapshotContext) -> bool),
+}
+
+impl SnapshotPolicy {
+    /// Whether a snapshot should be taken at the version of the context.
+    pub fn should_snapshot(&self, context: &SnapshotContext) -> bool {
+        if context.events_since_snapshot() <= 0 {
+            return false;
+        }
+        match self {
+            SnapshotPolicy::EveryNEvents(events) => context.events_since_snapshot() >= *events,
+            SnapshotPolicy::ReplayDuration(duration) => context.replay_duration >= *duration,
+            SnapshotPolicy::Custom(predicate) => predicate(context),
+        }
+    }
+}
+
+/// How a snapshot is persisted once a policy fires.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum SnapshotMode {
+    // Persist the snapshot before the commit returns.  The events have been saved by then, so an
+    // error taking the snapshot is handed to the snapshot error handler instead of being returned.
+    #[default]
+    Inline,
+    // Hand the snapshot to the spawner and return immediately.  Failures are handed to the snapshot
+    // error handler as well.
+    Background(Spawn),
+}
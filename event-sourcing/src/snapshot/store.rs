@@ -1,19 +1,74 @@
-use crate::event::envelope::EventEnvelope;
+pub mod in_memory;
+
+use chrono::{DateTime, Utc};
+
+use crate::aggregate::Aggregate;
+use crate::snapshot::envelope::SnapshotEnvelope;
 use crate::Error;
-use serde::de::DeserializeOwned;
-use serde::Serialize;
-use crate::event::EventType;
 
+#[allow(clippy::ptr_arg)]
 #[async_trait::async_trait]
 pub trait SnapshotStore: Sized + Send + Sync + Clone {
-    // Fetch the latest snapshot version for the aggregate.
-    async fn read<Aggregate: EventType + Serialize + DeserializeOwned>(
+    // Fetch the latest snapshot of the aggregate, or `None` when it does not have a snapshot.
+    async fn read<A: Aggregate>(
+        &self,
+        aggregate_id: &String,
+    ) -> Result<Option<SnapshotEnvelope<A>>, Error>;
+    // Fetch the latest snapshot of the aggregate taken at or before the version.  Stores that only
+    // keep the latest snapshot return it when it is old enough.
+    async fn read_at_version<A: Aggregate>(
         &self,
         aggregate_id: &String,
-    ) -> Result<EventEnvelope<Aggregate>, Error>;
-    // Persist a snapshot for the aggregate.
-    async fn persist<Aggregate: EventType + Serialize + DeserializeOwned>(
+        version: i64,
+    ) -> Result<Option<SnapshotEnvelope<A>>, Error> {
+        Ok(self
+            .read::<A>(aggregate_id)
+            .await?
+            .filter(|snapshot_envelope| snapshot_envelope.version <= version))
+    }
+    // Fetch the latest snapshot of the aggregate created at or before the timestamp, which only
+    // holds events that happened before it.  Stores that only keep the latest snapshot return it
+    // when it is old enough.
+    async fn read_at_timestamp<A: Aggregate>(
         &self,
-        snapshot_envelope: EventEnvelope<Aggregate>,
+        aggregate_id: &String,
+        timestamp: DateTime<Utc>,
+    ) -> Result<Option<SnapshotEnvelope<A>>, Error> {
+        Ok(self
+            .read::<A>(aggregate_id)
+            .await?
+            .filter(|snapshot_envelope| snapshot_envelope.timestamp <= timestamp))
+    }
+    // Persist a snapshot of the aggregate, replacing any snapshot taken at the same version.
+    async fn persist<A: Aggregate>(
+        &self,
+        snapshot_envelope: SnapshotEnvelope<A>,
     ) -> Result<(), Error>;
+    // Erase every snapshot of the aggregate, e.g. once its stream has been hard deleted.
+    async fn delete(&self, aggregate_id: &String) -> Result<(), Error>;
+}
+
+/// Snapshot store that never holds a snapshot, for aggregates that are always rebuilt from their events.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoSnapshotStore;
+
+#[async_trait::async_trait]
+impl SnapshotStore for NoSnapshotStore {
+    async fn read<A: Aggregate>(
+        &self,
+        _aggregate_id: &String,
+    ) -> Result<Option<SnapshotEnvelope<A>>, Error> {
+        Ok(None)
+    }
+
+    async fn persist<A: Aggregate>(
+        &self,
+        _snapshot_envelope: SnapshotEnvelope<A>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn delete(&self, _aggregate_id: &String) -> Result<(), Error> {
+        Ok(())
+    }
 }
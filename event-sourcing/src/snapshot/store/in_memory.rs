@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+
+use crate::aggregate::Aggregate;
+use crate::codec::{Codec, ContentType, JsonCodec};
+use crate::snapshot::envelope::{decode, encode, SnapshotEnvelope};
+use crate::snapshot::migrator::SnapshotMigratorRegistry;
+use crate::snapshot::store::SnapshotStore;
+use crate::Error;
+
+/// Snapshot store that keeps every snapshot in memory, intended for tests and local development.
+///
+/// Clones share the same underlying state, so a single store can be handed to many tasks.
+///
+/// # Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use uuid::Uuid;
+/// # use event_sourcing::Error;
+/// # use serde::{Deserialize, Serialize};
+/// # use event_sourcing::aggregate::Aggregate;
+/// # use event_sourcing::event::EventType;
+/// # use event_sourcing::snapshot::envelope::SnapshotEnvelope;
+/// # use event_sourcing::snapshot::store::SnapshotStore;
+/// # use event_sourcing::snapshot::store::in_memory::InMemorySnapshotStore;
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// # struct TestEvent {
+/// #     id: Uuid,
+/// #     amount: i64,
+/// #     description: String,
+/// # }
+///
+/// # impl EventType for TestEvent {
+/// #     fn event_type(&self) -> String {
+/// #         String::from("TestEvent")
+/// #     }
+/// # }
+///
+/// # #[derive(Debug, Clone, Serialize, Deserialize)]
+/// # struct TestAggregate {
+/// #     id: Uuid,
+/// #     total: i64,
+/// # }
+///
+/// # impl Aggregate for TestAggregate {
+/// #     type AggregateID = Uuid;
+/// #     type Event = TestEvent;
+/// #     type Error = Error;
+/// #
+/// #     fn aggregate_type() -> String {
+/// #         String::from("TestAggregate")
+/// #     }
+/// #
+/// #     fn aggregate_id(&self) -> &Self::AggregateID {
+/// #         &self.id
+/// #     }
+/// #
+/// #     fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error> {
+/// #         match state {
+/// #             None => Ok(Self { id: event.id, total: event.amount }),
+/// #             Some(mut state) => {
+/// #                 state.total += event.amount;
+/// #                 Ok(state)
+/// #             }
+/// #         }
+/// #     }
+/// #
+/// #     fn apply_all(events: Vec<Self::Event>) -> Result<Self, Self::Error> {
+/// #         events
+/// #             .into_iter()
+/// #             .try_fold(None, |state, event| Self::apply(state, event).map(Some))?
+/// #             .ok_or_else(|| Error::from("Aggregate must not be None"))
+/// #     }
+/// # }
+///
+/// # futures::executor::block_on(async {
+/// # let id = Uuid::from_str("2e996ba1-03a6-47af-8fd1-2039c6708dd4").expect("expected uuid");
+/// let snapshot_store = InMemorySnapshotStore::default();
+/// snapshot_store
+///     .persist(SnapshotEnvelope::new(
+///         id.to_string(),
+///         TestAggregate::aggregate_type(),
+///         TestAggregate { id, total: 1 },
+///         3,
+///     ))
+///     .await
+///     .expect("expected persisted snapshot");
+/// let snapshot_envelope = snapshot_store
+///     .read::<TestAggregate>(&id.to_string())
+///     .await
+///     .expect("expected snapshot");
+///
+/// # assert_eq!(snapshot_envelope.map(|snapshot_envelope| snapshot_envelope.version), Some(3));
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct InMemorySnapshotStore<C = JsonCodec> {
+    snapshots: Arc<RwLock<HashMap<String, Vec<StoredSnapshot>>>>,
+    codec: C,
+    // Migrators applied to snapshots taken at an older schema version of their aggregate.
+    migrators: SnapshotMigratorRegistry,
+}
+
+impl Default for InMemorySnapshotStore {
+    fn default() -> Self {
+        Self {
+            snapshots: Arc::default(),
+            codec: JsonCodec,
+            migrators: SnapshotMigratorRegistry::default(),
+        }
+    }
+}
+
+// Encoded snapshot kept alongside its version and timestamp so snapshots can be ordered and looked
+// up without knowing the aggregate type, and alongside its content type so it can be decoded
+// whatever codec wrote it.
+#[derive(Debug, Clone)]
+struct StoredSnapshot {
+    version: i64,
+    timestamp: DateTime<Utc>,
+    content_type: String,
+    envelope: Vec<u8>,
+}
+
+impl<C: Codec> InMemorySnapshotStore<C> {
+    /// Encode new snapshots with the codec.  Snapshots that have already been stored keep the
+    /// content type they were written with.
+    pub fn with_codec<D: Codec>(self, codec: D) -> InMemorySnapshotStore<D> {
+        InMemorySnapshotStore {
+            snapshots: self.snapshots,
+            codec,
+            migrators: self.migrators,
+        }
+    }
+
+    /// Migrate snapshots taken at an older schema version with the given migrators.  Snapshots that
+    /// cannot be migrated are ignored.
+    pub fn with_migrators(mut self, migrators: SnapshotMigratorRegistry) -> Self {
+        self.migrators = migrators;
+        self
+    }
+
+    // Decode the latest snapshot of the aggregate that matches the predicate.
+    fn read_latest<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+        predicate: impl Fn(&StoredSnapshot) -> bool,
+    ) -> Result<Option<SnapshotEnvelope<A>>, Error> {
+        let snapshots = self
+            .snapshots
+            .read()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        snapshots
+            .get(aggregate_id)
+            .and_then(|snapshots| {
+                snapshots
+                    .iter()
+                    .rev()
+                    .find(|stored_snapshot| predicate(stored_snapshot))
+            })
+            .map(|stored_snapshot| self.decode(stored_snapshot))
+            .transpose()
+            .map(Option::flatten)
+    }
+
+    // Decode the snapshot with the codec of the store when it wrote it, and with the built-in codec
+    // of its content type otherwise.
+    fn decode<A: Aggregate>(
+        &self,
+        stored_snapshot: &StoredSnapshot,
+    ) -> Result<Option<SnapshotEnvelope<A>>, Error> {
+        if stored_snapshot.content_type == self.codec.content_type() {
+            return decode(&self.codec, &stored_snapshot.envelope, &self.migrators);
+        }
+        decode(
+            &stored_snapshot.content_type.parse::<ContentType>()?,
+            &stored_snapshot.envelope,
+            &self.migrators,
+        )
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: Codec + 'static> SnapshotStore for InMemorySnapshotStore<C> {
+    async fn read<A: Aggregate>(
+        &self,
+        aggregate_id: &String,
+    ) -> Result<Option<SnapshotEnvelope<A>>, Error> {
+        self.read_latest(aggregate_id, |_| true)
+    }
+
+    async fn read_at_version<A: Aggregate>(
+        &self,
+        aggregate_id: &String,
+        version: i64,
+    ) -> Result<Option<SnapshotEnvelope<A>>, Error> {
+        self.read_latest(aggregate_id, |stored_snapshot| {
+            stored_snapshot.version <= version
+        })
+    }
+
+    async fn read_at_timestamp<A: Aggregate>(
+        &self,
+        aggregate_id: &String,
+        timestamp: DateTime<Utc>,
+    ) -> Result<Option<SnapshotEnvelope<A>>, Error> {
+        self.read_latest(aggregate_id, |stored_snapshot| {
+            stored_snapshot.timestamp <= timestamp
+        })
+    }
+
+    async fn persist<A: Aggregate>(
+        &self,
+        snapshot_envelope: SnapshotEnvelope<A>,
+    ) -> Result<(), Error> {
+        let stored_snapshot = StoredSnapshot {
+            version: snapshot_envelope.version,
+            timestamp: snapshot_envelope.timestamp,
+            content_type: String::from(self.codec.content_type()),
+            envelope: encode(&snapshot_envelope, &self.codec)?,
+        };
+        let mut snapshots = self
+            .snapshots
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        let snapshots = snapshots.entry(snapshot_envelope.aggregate_id).or_default();
+        let index =
+            snapshots.partition_point(|existing| existing.version < stored_snapshot.version);
+        match snapshots.get_mut(index) {
+            Some(existing) if existing.version == stored_snapshot.version => {
+                *existing = stored_snapshot
+            }
+            _ => snapshots.insert(index, stored_snapshot),
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, aggregate_id: &String) -> Result<(), Error> {
+        let mut snapshots = self
+            .snapshots
+            .write()
+            .map_err(|error| Error::backend(error.to_string()))?;
+        snapshots.remove(aggregate_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::fixtures::{test_id, TestAggregate, TestEvent};
+
+    fn snapshot_envelope(total: i64, version: i64) -> SnapshotEnvelope<TestAggregate> {
+        let id = test_id();
+        SnapshotEnvelope::new(
+            id.to_string(),
+            TestAggregate::aggregate_type(),
+            TestAggregate { id, total },
+            version,
+        )
+    }
+
+    #[tokio::test]
+    async fn it_reads_nothing_without_snapshot() {
+        let snapshot_store = InMemorySnapshotStore::default();
+        let snapshot_envelope = snapshot_store
+            .read::<TestAggregate>(&String::from("2e996ba1-03a6-47af-8fd1-2039c6708dd4"))
+            .await
+            .expect("expected no error");
+        assert!(snapshot_envelope.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_reads_latest_snapshot() {
+        let snapshot_store = InMemorySnapshotStore::default();
+        for (total, version) in [(10, 10), (30, 30), (20, 20)] {
+            snapshot_store
+                .persist(snapshot_envelope(total, version))
+                .await
+                .expect("expected persisted snapshot");
+        }
+        let snapshot_envelope = snapshot_store
+            .read::<TestAggregate>(&String::from("2e996ba1-03a6-47af-8fd1-2039c6708dd4"))
+            .await
+            .expect("expected no error")
+            .expect("expected snapshot");
+        assert_eq!(snapshot_envelope.version, 30);
+        assert_eq!(snapshot_envelope.data.total, 30);
+    }
+
+    #[tokio::test]
+    async fn it_replaces_snapshot_at_same_version() {
+        let snapshot_store = InMemorySnapshotStore::default();
+        for total in [1, 2] {
+            snapshot_store
+                .persist(snapshot_envelope(total, 5))
+                .await
+                .expect("expected persisted snapshot");
+        }
+        let snapshot_envelope = snapshot_store
+            .read::<TestAggregate>(&String::from("2e996ba1-03a6-47af-8fd1-2039c6708dd4"))
+            .await
+            .expect("expected no error")
+            .expect("expected snapshot");
+        assert_eq!(snapshot_envelope.data.total, 2);
+    }
+
+    #[tokio::test]
+    async fn it_reads_closest_earlier_snapshot() {
+        let snapshot_store = InMemorySnapshotStore::default();
+        for (total, version) in [(10, 10), (30, 30), (20, 20)] {
+            snapshot_store
+                .persist(snapshot_envelope(total, version))
+                .await
+                .expect("expected persisted snapshot");
+        }
+        let snapshot_envelope = snapshot_store
+            .read_at_version::<TestAggregate>(
+                &String::from("2e996ba1-03a6-47af-8fd1-2039c6708dd4"),
+                25,
+            )
+            .await
+            .expect("expected no error")
+            .expect("expected snapshot");
+        assert_eq!(snapshot_envelope.version, 20);
+        let snapshot_envelope = snapshot_store
+            .read_at_version::<TestAggregate>(
+                &String::from("2e996ba1-03a6-47af-8fd1-2039c6708dd4"),
+                9,
+            )
+            .await
+            .expect("expected no error");
+        assert!(snapshot_envelope.is_none());
+        let snapshot_envelope = snapshot_store
+            .read_at_timestamp::<TestAggregate>(
+                &String::from("2e996ba1-03a6-47af-8fd1-2039c6708dd4"),
+                Utc::now(),
+            )
+            .await
+            .expect("expected no error")
+            .expect("expected snapshot");
+        assert_eq!(snapshot_envelope.version, 30);
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct TestAccount {
+        id: Uuid,
+        balance: i64,
+    }
+
+    impl Aggregate for TestAccount {
+        type AggregateID = Uuid;
+        type Event = TestEvent;
+        type Error = Error;
+
+        fn aggregate_type() -> String {
+            String::from("TestAggregate")
+        }
+
+        fn aggregate_id(&self) -> &Self::AggregateID {
+            &self.id
+        }
+
+        fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error> {
+            match state {
+                None => Ok(Self {
+                    id: event.id,
+                    balance: event.amount,
+                }),
+                Some(mut state) => {
+                    state.balance += event.amount;
+                    Ok(state)
+                }
+            }
+        }
+
+        fn apply_all(events: Vec<Self::Event>) -> Result<Self, Self::Error> {
+            events
+                .into_iter()
+                .try_fold(None, |state, event| Self::apply(state, event).map(Some))?
+                .ok_or_else(|| Error::from("Aggregate must not be None"))
+        }
+
+        fn snapshot_schema_version() -> i64 {
+            2
+        }
+    }
+
+    fn rename_total(mut data: serde_json::Value) -> Result<serde_json::Value, Error> {
+        let object = data
+            .as_object_mut()
+            .ok_or_else(|| Error::serialization("expected an object"))?;
+        let total = object.remove("total").unwrap_or_default();
+        object.insert(String::from("balance"), total);
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn it_ignores_snapshot_of_older_schema_version() {
+        let snapshot_store = InMemorySnapshotStore::default();
+        snapshot_store
+            .persist(snapshot_envelope(5, 5))
+            .await
+            .expect("expected persisted snapshot");
+        let snapshot_envelope = snapshot_store
+            .read::<TestAccount>(&String::from("2e996ba1-03a6-47af-8fd1-2039c6708dd4"))
+            .await
+            .expect("expected no error");
+        assert!(snapshot_envelope.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_migrates_snapshot_of_older_schema_version() {
+        let snapshot_store = InMemorySnapshotStore::default().with_migrators(
+            SnapshotMigratorRegistry::default().register("TestAggregate", 1, rename_total),
+        );
+        snapshot_store
+            .persist(snapshot_envelope(5, 5))
+            .await
+            .expect("expected persisted snapshot");
+        let snapshot_envelope = snapshot_store
+            .read::<TestAccount>(&String::from("2e996ba1-03a6-47af-8fd1-2039c6708dd4"))
+            .await
+            .expect("expected no error")
+            .expect("expected migrated snapshot");
+        assert_eq!(snapshot_envelope.schema_version, 2);
+        assert_eq!(snapshot_envelope.version, 5);
+        assert_eq!(snapshot_envelope.data.balance, 5);
+    }
+}
@@ -1,39 +1,1450 @@
-use event_sourcing::event::envelope::EventEnvelope;
-use event_sourcing::event::store::EventStore;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use cdrs_tokio::cluster::session::{Session, SessionBuilder, TcpSessionBuilder};
+use cdrs_tokio::cluster::{NodeTcpConfigBuilder, TcpConnectionManager};
+use cdrs_tokio::frame::message_error::AdditionalErrorInfo;
+use cdrs_tokio::frame::Envelope;
+use cdrs_tokio::load_balancing::RoundRobinLoadBalancingStrategy;
+use cdrs_tokio::query::{BatchQueryBuilder, QueryParamsBuilder, QueryValues};
+use cdrs_tokio::query_values;
+use cdrs_tokio::transport::TransportTcp;
+use cdrs_tokio::types::blob::Blob;
+use cdrs_tokio::types::rows::Row;
+use cdrs_tokio::types::value::Value;
+use cdrs_tokio::types::IntoRustByName;
+use chrono::{DateTime, Utc};
+use event_sourcing::codec::{Codec, ContentType, JsonCodec};
+use event_sourcing::event::envelope::{check_codec, EventEnvelope};
+use event_sourcing::event::metadata::{EventMetadata, StreamMetadata};
+use event_sourcing::event::registry::deserialize_data;
+use event_sourcing::event::store::{
+    check_versions, paged_stream, EventBatch, EventFilter, EventStore, ExpectedVersion,
+    VersionConflictError,
+};
+use event_sourcing::event::upcaster::UpcasterRegistry;
+use event_sourcing::event::EventType;
 use event_sourcing::Error;
+use futures::stream::BoxStream;
+use rand::Rng;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use event_sourcing::event::EventType;
+use uuid::Uuid;
 
-#[derive(Clone)]
-pub struct CassandraEventStoreConfiguration {}
+type CassandraSession = Session<
+    TransportTcp,
+    TcpConnectionManager,
+    RoundRobinLoadBalancingStrategy<TransportTcp, TcpConnectionManager>,
+>;
+
+// Number of consecutive positions stored in a single partition of the by-position table.
+const POSITION_BUCKET_SIZE: i64 = 1000;
+
+// Maximum number of rows of the by-position table scanned by a single `read_all`, so that a selective
+// filter never pulls the whole log into memory at once.
+const SCAN_LIMIT: usize = 1000;
+
+// Maximum number of statements sent in a single batch, so that erasing many events never exceeds
+// the batch size limits of the cluster.
+const BATCH_SIZE: usize = 100;
+
+// Number of times positions are attempted to be reserved before giving up.
+const RESERVE_ATTEMPTS: u32 = 10;
+
+// Upper bound of the backoff after the first failed reservation, doubled after every further one.
+const RESERVE_BACKOFF: Duration = Duration::from_millis(10);
+
+// Key of the row of the positions table holding the latest reserved position.
+const POSITION_ID: &str = "global";
+
+// Columns of an event row, in the order they are bound by `event_values`.
+const EVENT_COLUMNS: &str =
+    "aggregate_id, version, id, aggregate_type, schema_version, content_type, \
+     data, event_type, timestamp, metadata, position";
 
+#[derive(Debug, Clone, derive_new::new)]
+pub struct CassandraEventStoreConfiguration {
+    // Addresses of the nodes to connect to, e.g. `127.0.0.1:9042`.
+    pub contact_points: Vec<String>,
+    // Keyspace containing the events table.
+    pub keyspace: String,
+    // Name of the events table.
+    pub table: String,
+}
+
+/// Event store backed by Apache Cassandra or ScyllaDB.
+///
+/// Events are stored one row per envelope, partitioned by aggregate and clustered by version.
+/// Writes use lightweight transactions so two writers can never store the same version.  The data of
+/// each event is encoded with the configured content type, which is recorded next to it.
+///
+/// Every event is also given a global position, reserved from a counter row before it is written,
+/// and copied to a table partitioned by buckets of positions so that all events can be read in order.
+/// The copy is written before the event itself and marked as committed or rejected once the write
+/// has been decided, so a writer that fails in between never loses an event from `read_all`; copies
+/// that have not been marked are checked against the events table.  Writers may commit out of order,
+/// so `read_all` holds back at a position that has not been written or decided yet, until the gap
+/// timeout has passed since it was reserved.  The store cannot notify subscriptions of new events,
+/// so they have to poll it with `CatchUpSubscription::with_polling`.
+///
+/// Deleted streams are recorded in a tombstones table.  Their events are marked as rejected in the
+/// by-position table, and removed from the events table when the stream is hard deleted.  The
+/// metadata of each stream is stored as JSON in a metadata table, and events that have expired
+/// according to it are left out of reads until a scavenge erases them.
+///
+/// The tombstone is checked with a separate query before events are written, so a `persist` or
+/// `append` racing with a delete of the same stream can still store its events after the stream
+/// has been deleted, and after its events have been erased by a hard delete.  Reads keep hiding the
+/// stream, but such events are only erased by deleting it again.  Stop writing to a stream before
+/// deleting it, e.g. by handling the commands of its aggregate on a single node.
+///
+/// ```cql
+/// CREATE TABLE IF NOT EXISTS <keyspace>.<table> (
+///     aggregate_id text,
+///     version bigint,
+///     id uuid,
+///     aggregate_type text,
+///     schema_version bigint,
+///     content_type text,
+///     data blob,
+///     event_type text,
+///     timestamp timestamp,
+///     metadata text,
+///     position bigint,
+///     PRIMARY KEY (aggregate_id, version)
+/// ) WITH CLUSTERING ORDER BY (version ASC) AND cdc = true;
+///
+/// CREATE TABLE IF NOT EXISTS <keyspace>.<table>_positions (
+///     id text PRIMARY KEY,
+///     position bigint
+/// );
+///
+/// CREATE TABLE IF NOT EXISTS <keyspace>.<table>_by_position (
+///     bucket bigint,
+///     position bigint,
+///     aggregate_id text,
+///     version bigint,
+///     id uuid,
+///     aggregate_type text,
+///     schema_version bigint,
+///     content_type text,
+///     data blob,
+///     event_type text,
+///     timestamp timestamp,
+///     metadata text,
+///     committed boolean,
+///     reserved_at timestamp,
+///     PRIMARY KEY (bucket, position)
+/// ) WITH CLUSTERING ORDER BY (position ASC);
+///
+/// CREATE TABLE IF NOT EXISTS <keyspace>.<table>_tombstones (
+///     aggregate_id text PRIMARY KEY,
+///     hard boolean
+/// );
+///
+/// CREATE TABLE IF NOT EXISTS <keyspace>.<table>_metadata (
+///     aggregate_id text PRIMARY KEY,
+///     metadata text
+/// );
+/// ```
+///
+/// # Migrating from text data
+///
+/// The `data` column used to be `text` holding JSON, and is now a `blob`.  Cassandra cannot change
+/// the type of an existing column, so tables created before this change have to be copied into
+/// tables created with the schema above before this version writes to them, e.g. with a Spark job
+/// or a program that reads every row and writes it back with `textAsBlob(data)`.  The JSON bytes are
+/// kept as they are and the `content_type` of the copied rows is left empty, so they are decoded as
+/// JSON.  Debezium emits blob columns as base64, which `KafkaEventStream` expects.
 #[derive(Clone)]
-pub struct CassandraEventStore {
+pub struct CassandraEventStore<C = JsonCodec> {
     pub configuration: CassandraEventStoreConfiguration,
+    session: Arc<CassandraSession>,
+    upcasters: UpcasterRegistry,
+    codec: C,
+    // Number of rows fetched at a time when a stream is read as a `Stream` or scavenged.
+    page_size: i32,
+    // How long `read_all` holds back at a position that is still being written.
+    gap_timeout: Duration,
+}
+
+impl CassandraEventStore {
+    /// Connect to the cluster described by the configuration.
+    pub async fn new(configuration: CassandraEventStoreConfiguration) -> Result<Self, Error> {
+        let node_configuration = NodeTcpConfigBuilder::new()
+            .with_contact_points(
+                configuration
+                    .contact_points
+                    .iter()
+                    .map(|contact_point| contact_point.into())
+                    .collect(),
+            )
+            .build()
+            .await
+            .map_err(CassandraEventStoreError::from)?;
+        let session =
+            TcpSessionBuilder::new(RoundRobinLoadBalancingStrategy::new(), node_configuration)
+                .build()
+                .map_err(Error::backend)?;
+        Ok(Self {
+            configuration,
+            session: Arc::new(session),
+            upcasters: UpcasterRegistry::default(),
+            codec: JsonCodec,
+            page_size: 500,
+            gap_timeout: Duration::from_secs(10),
+        })
+    }
+}
+
+impl<C: Codec> CassandraEventStore<C> {
+    /// Upcast events to their current shape when they are read.
+    pub fn with_upcasters(mut self, upcasters: UpcasterRegistry) -> Self {
+        self.upcasters = upcasters;
+        self
+    }
+
+    /// Encode the data of new events with the codec.  Events that have already been stored keep
+    /// the content type they were written with.
+    pub fn with_codec<D: Codec>(self, codec: D) -> CassandraEventStore<D> {
+        CassandraEventStore {
+            configuration: self.configuration,
+            session: self.session,
+            upcasters: self.upcasters,
+            codec,
+            page_size: self.page_size,
+            gap_timeout: self.gap_timeout,
+        }
+    }
+
+    /// Fetch streamed and scavenged events `page_size` rows at a time.
+    pub fn with_page_size(mut self, page_size: i32) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Hold back `read_all` at a position that is still being written for up to `gap_timeout` after
+    /// it was reserved, then skip it as abandoned.  It has to exceed the time a writer takes to store
+    /// its events.
+    pub fn with_gap_timeout(mut self, gap_timeout: Duration) -> Self {
+        self.gap_timeout = gap_timeout;
+        self
+    }
+
+    fn table(&self) -> String {
+        format!(
+            "{}.{}",
+            self.configuration.keyspace, self.configuration.table
+        )
+    }
+
+    fn positions_table(&self) -> String {
+        format!("{}_positions", self.table())
+    }
+
+    fn by_position_table(&self) -> String {
+        format!("{}_by_position", self.table())
+    }
+
+    fn tombstones_table(&self) -> String {
+        format!("{}_tombstones", self.table())
+    }
+
+    fn metadata_table(&self) -> String {
+        format!("{}_metadata", self.table())
+    }
+
+    async fn select<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &str,
+        version: i64,
+    ) -> Result<Vec<EventEnvelope<Event>>, CassandraEventStoreError> {
+        if self.is_deleted(aggregate_id).await? {
+            return Ok(Vec::new());
+        }
+        let query = format!(
+            "SELECT {} FROM {} WHERE aggregate_id = ? AND version >= ?",
+            EVENT_COLUMNS,
+            self.table()
+        );
+        let event_envelopes = self
+            .session
+            .query_with_values(query, query_values!(aggregate_id, version))
+            .await?
+            .response_body()?
+            .into_rows()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|row| self.event_envelope(row).transpose())
+            .collect::<Result<Vec<EventEnvelope<Event>>, CassandraEventStoreError>>()?;
+        self.apply_retention(aggregate_id, event_envelopes).await
+    }
+
+    async fn select_range<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &str,
+        from_version: i64,
+        to_version: i64,
+    ) -> Result<Vec<EventEnvelope<Event>>, CassandraEventStoreError> {
+        if self.is_deleted(aggregate_id).await? {
+            return Ok(Vec::new());
+        }
+        let query = format!(
+            "SELECT {} FROM {} WHERE aggregate_id = ? AND version >= ? AND version <= ?",
+            EVENT_COLUMNS,
+            self.table()
+        );
+        let event_envelopes = self
+            .session
+            .query_with_values(query, query_values!(aggregate_id, from_version, to_version))
+            .await?
+            .response_body()?
+            .into_rows()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|row| self.event_envelope(row).transpose())
+            .collect::<Result<Vec<EventEnvelope<Event>>, CassandraEventStoreError>>()?;
+        self.apply_retention(aggregate_id, event_envelopes).await
+    }
+
+    async fn select_backward<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &str,
+        version: i64,
+        limit: usize,
+    ) -> Result<Vec<EventEnvelope<Event>>, CassandraEventStoreError> {
+        if self.is_deleted(aggregate_id).await? {
+            return Ok(Vec::new());
+        }
+        let query = format!(
+            "SELECT {} FROM {} WHERE aggregate_id = ? AND version <= ? \
+             ORDER BY version DESC LIMIT ?",
+            EVENT_COLUMNS,
+            self.table()
+        );
+        let limit = i32::try_from(limit).unwrap_or(i32::MAX);
+        let event_envelopes = self
+            .session
+            .query_with_values(query, query_values!(aggregate_id, version, limit))
+            .await?
+            .response_body()?
+            .into_rows()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|row| self.event_envelope(row).transpose())
+            .collect::<Result<Vec<EventEnvelope<Event>>, CassandraEventStoreError>>()?;
+        self.apply_retention(aggregate_id, event_envelopes).await
+    }
+
+    // Select up to `page_size` events on and after the version, along with the version of the next
+    // page when there may be one.
+    async fn select_page<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &str,
+        version: i64,
+    ) -> Result<(Vec<EventEnvelope<Event>>, Option<i64>), CassandraEventStoreError> {
+        if self.is_deleted(aggregate_id).await? {
+            return Ok((Vec::new(), None));
+        }
+        let query = format!(
+            "SELECT {} FROM {} WHERE aggregate_id = ? AND version >= ? LIMIT ?",
+            EVENT_COLUMNS,
+            self.table()
+        );
+        let rows = self
+            .session
+            .query_with_values(query, query_values!(aggregate_id, version, self.page_size))
+            .await?
+            .response_body()?
+            .into_rows()
+            .unwrap_or_default();
+        let next_version = match rows.last() {
+            Some(row) if rows.len() == self.page_size as usize => {
+                let version: i64 = row.get_r_by_name("version")?;
+                Some(version + 1)
+            }
+            _ => None,
+        };
+        let event_envelopes = rows
+            .into_iter()
+            .filter_map(|row| self.event_envelope(row).transpose())
+            .collect::<Result<Vec<EventEnvelope<Event>>, CassandraEventStoreError>>()?;
+        let event_envelopes = self.apply_retention(aggregate_id, event_envelopes).await?;
+        Ok((event_envelopes, next_version))
+    }
+
+    async fn current_version(&self, aggregate_id: &str) -> Result<i64, CassandraEventStoreError> {
+        let query = format!(
+            "SELECT version FROM {} WHERE aggregate_id = ? ORDER BY version DESC LIMIT 1",
+            self.table()
+        );
+        let rows = self
+            .session
+            .query_with_values(query, query_values!(aggregate_id))
+            .await?
+            .response_body()?
+            .into_rows()
+            .unwrap_or_default();
+        match rows.first() {
+            Some(row) => Ok(row.get_r_by_name("version")?),
+            None => Ok(0),
+        }
+    }
+
+    async fn select_metadata(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<Option<StreamMetadata>, CassandraEventStoreError> {
+        let query = format!(
+            "SELECT metadata FROM {} WHERE aggregate_id = ?",
+            self.metadata_table()
+        );
+        let rows = self
+            .session
+            .query_with_values(query, query_values!(aggregate_id))
+            .await?
+            .response_body()?
+            .into_rows()
+            .unwrap_or_default();
+        match rows.first() {
+            Some(row) => {
+                let metadata: String = row.get_r_by_name("metadata")?;
+                Ok(Some(serde_json::from_str(&metadata)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Metadata of the stream of the aggregate along with its current version, or `None` when the
+    // metadata does not expire any events.
+    async fn retention(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<Option<(StreamMetadata, i64)>, CassandraEventStoreError> {
+        match self.select_metadata(aggregate_id).await? {
+            Some(metadata) if metadata.has_retention() => {
+                Ok(Some((metadata, self.current_version(aggregate_id).await?)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Leave out the events of the aggregate that have expired according to the metadata of its
+    // stream.
+    async fn apply_retention<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &str,
+        mut event_envelopes: Vec<EventEnvelope<Event>>,
+    ) -> Result<Vec<EventEnvelope<Event>>, CassandraEventStoreError> {
+        if let Some((metadata, current_version)) = self.retention(aggregate_id).await? {
+            let now = Utc::now();
+            event_envelopes.retain(|event_envelope| {
+                metadata.retains(
+                    event_envelope.version,
+                    event_envelope.timestamp,
+                    current_version,
+                    now,
+                )
+            });
+        }
+        Ok(event_envelopes)
+    }
+
+    // Erase the expired events of the aggregate, keeping its latest event, and return how many
+    // were erased.  The events are scanned `page_size` rows at a time.
+    async fn scavenge_stream(
+        &self,
+        aggregate_id: &str,
+        metadata: &StreamMetadata,
+    ) -> Result<usize, CassandraEventStoreError> {
+        let current_version = self.current_version(aggregate_id).await?;
+        let now = Utc::now();
+        let query = format!(
+            "SELECT version, timestamp, position FROM {} WHERE aggregate_id = ?",
+            self.table()
+        );
+        let delete_event = format!(
+            "DELETE FROM {} WHERE aggregate_id = ? AND version = ?",
+            self.table()
+        );
+        let discard_position = format!(
+            "UPDATE {} SET committed = false, data = null, metadata = null \
+             WHERE bucket = ? AND position = ?",
+            self.by_position_table()
+        );
+        let mut pager = self.session.paged(self.page_size);
+        let mut rows = pager.query_with_params(
+            query,
+            QueryParamsBuilder::new()
+                .with_values(query_values!(aggregate_id))
+                .build(),
+        );
+        let mut scavenged = 0;
+        loop {
+            let mut queries = Vec::new();
+            for row in rows.next().await? {
+                let version: i64 = row.get_r_by_name("version")?;
+                let timestamp: DateTime<Utc> = row.get_r_by_name("timestamp")?;
+                if metadata.retains(version, timestamp, current_version, now) {
+                    continue;
+                }
+                // Rows written before the position column existed were never copied by position.
+                // The copy is discarded before the event, so that an event left over by a failed
+                // batch is erased by the next scavenge.
+                let position: Option<i64> = row.get_by_name("position")?;
+                if let Some(position) = position {
+                    queries.push((
+                        discard_position.clone(),
+                        query_values!(position / POSITION_BUCKET_SIZE, position),
+                    ));
+                }
+                queries.push((delete_event.clone(), query_values!(aggregate_id, version)));
+                scavenged += 1;
+            }
+            self.batch(queries).await?;
+            if !rows.has_more() {
+                return Ok(scavenged);
+            }
+        }
+    }
+
+    // Send the queries in batches of up to `BATCH_SIZE` statements.  The batches are applied one
+    // after the other, so the queries have to be safe to send again when a later batch fails.
+    async fn batch(
+        &self,
+        queries: Vec<(String, QueryValues)>,
+    ) -> Result<(), CassandraEventStoreError> {
+        let mut queries = queries.into_iter().peekable();
+        while queries.peek().is_some() {
+            let batch = queries
+                .by_ref()
+                .take(BATCH_SIZE)
+                .fold(BatchQueryBuilder::new(), |batch, (query, values)| {
+                    batch.add_query(query, values)
+                });
+            self.session.batch(batch.build()?).await?;
+        }
+        Ok(())
+    }
+
+    // Whether the stream of the aggregate has been deleted.
+    async fn is_deleted(&self, aggregate_id: &str) -> Result<bool, CassandraEventStoreError> {
+        let query = format!(
+            "SELECT aggregate_id FROM {} WHERE aggregate_id = ?",
+            self.tombstones_table()
+        );
+        let rows = self
+            .session
+            .query_with_values(query, query_values!(aggregate_id))
+            .await?
+            .response_body()?
+            .into_rows()
+            .unwrap_or_default();
+        Ok(!rows.is_empty())
+    }
+
+    // Fail with `Error::StreamDeleted` when the stream of the aggregate has been deleted, or with a
+    // version conflict when it is not at the expected version.
+    async fn check_writable(
+        &self,
+        aggregate_id: &str,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), CassandraEventStoreError> {
+        if self.is_deleted(aggregate_id).await? {
+            return Err(Error::StreamDeleted(String::from(aggregate_id)).into());
+        }
+        if expected_version == ExpectedVersion::Any {
+            return Ok(());
+        }
+        let current_version = self.current_version(aggregate_id).await?;
+        if !expected_version.is_satisfied_by(current_version) {
+            return Err(Error::from(VersionConflictError::new(
+                String::from(aggregate_id),
+                expected_version,
+                current_version,
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    // Mark the events of the aggregate before the version as rejected in the by-position table and
+    // erase their copies.  The events are scanned `page_size` rows at a time.
+    async fn discard_positions(
+        &self,
+        aggregate_id: &str,
+        version: i64,
+    ) -> Result<(), CassandraEventStoreError> {
+        let query = format!(
+            "SELECT position FROM {} WHERE aggregate_id = ? AND version < ?",
+            self.table()
+        );
+        let discard_position = format!(
+            "UPDATE {} SET committed = false, data = null, metadata = null \
+             WHERE bucket = ? AND position = ?",
+            self.by_position_table()
+        );
+        let mut pager = self.session.paged(self.page_size);
+        let mut rows = pager.query_with_params(
+            query,
+            QueryParamsBuilder::new()
+                .with_values(query_values!(aggregate_id, version))
+                .build(),
+        );
+        loop {
+            let mut queries = Vec::new();
+            for row in rows.next().await? {
+                // Rows written before the position column existed were never copied by position.
+                let position: Option<i64> = row.get_by_name("position")?;
+                if let Some(position) = position {
+                    queries.push((
+                        discard_position.clone(),
+                        query_values!(position / POSITION_BUCKET_SIZE, position),
+                    ));
+                }
+            }
+            self.batch(queries).await?;
+            if !rows.has_more() {
+                return Ok(());
+            }
+        }
+    }
+
+    // Select events of every aggregate on and after the position, bucket by bucket, until `limit`
+    // events matching the filter have been found, `SCAN_LIMIT` rows have been scanned, the latest
+    // reserved position has been reached or a position that is still being written has been reached.
+    // The batch may be empty, in which case its next position still moves past the scanned rows.
+    async fn select_all<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        from_position: i64,
+        limit: usize,
+        filter: &EventFilter,
+    ) -> Result<EventBatch<Event>, CassandraEventStoreError> {
+        let query = format!(
+            "SELECT {}, committed, reserved_at FROM {} WHERE bucket = ? AND position >= ? LIMIT ?",
+            EVENT_COLUMNS,
+            self.by_position_table()
+        );
+        let last_bucket = self.current_position().await? / POSITION_BUCKET_SIZE;
+        let mut event_envelopes = Vec::new();
+        let mut streams = HashMap::new();
+        let now = Utc::now();
+        // Positions start at 1, and the next one may not have been written yet.
+        let mut next_position = from_position.max(1);
+        let mut bucket = next_position / POSITION_BUCKET_SIZE;
+        let mut scanned = 0;
+        'buckets: while event_envelopes.len() < limit
+            && scanned < SCAN_LIMIT
+            && bucket <= last_bucket
+        {
+            // Never fetch more rows than are left to be found or scanned.
+            let page_size = (limit - event_envelopes.len()).min(SCAN_LIMIT - scanned);
+            let rows = self
+                .session
+                .query_with_values(
+                    query.clone(),
+                    query_values!(
+                        bucket,
+                        next_position,
+                        i32::try_from(page_size).unwrap_or(i32::MAX)
+                    ),
+                )
+                .await?
+                .response_body()?
+                .into_rows()
+                .unwrap_or_default();
+            // The bucket has more rows to scan when the page is full.
+            let exhausted = rows.len() < page_size;
+            for row in rows {
+                scanned += 1;
+                let position: i64 = row.get_r_by_name("position")?;
+                let reserved_at: Option<DateTime<Utc>> = row.get_by_name("reserved_at")?;
+                // Missing positions were reserved before this one, so they have been abandoned once
+                // it has.
+                if position > next_position && !is_abandoned(reserved_at, self.gap_timeout, now) {
+                    break 'buckets;
+                }
+                next_position = position + 1;
+                let aggregate_type: String = row.get_r_by_name("aggregate_type")?;
+                let event_type: String = row.get_r_by_name("event_type")?;
+                if !filter.matches(&aggregate_type, &event_type) {
+                    continue;
+                }
+                match self.resolve_position(&row, reserved_at, now).await? {
+                    Resolution::Deliver => {}
+                    Resolution::Skip => continue,
+                    Resolution::HoldBack => {
+                        // Resume at the held back position once its write has been decided.
+                        next_position = position;
+                        break 'buckets;
+                    }
+                }
+                let Some(event_envelope) = self.event_envelope::<Event>(row)? else {
+                    continue;
+                };
+                if !streams.contains_key(&event_envelope.aggregate_id) {
+                    // Events committed while their stream was being deleted may not have been
+                    // marked as rejected.
+                    let retention = if self.is_deleted(&event_envelope.aggregate_id).await? {
+                        None
+                    } else {
+                        Some(self.retention(&event_envelope.aggregate_id).await?)
+                    };
+                    streams.insert(event_envelope.aggregate_id.clone(), retention);
+                }
+                match streams.get(&event_envelope.aggregate_id) {
+                    Some(None) => continue,
+                    Some(Some(Some((metadata, current_version))))
+                        if !metadata.retains(
+                            event_envelope.version,
+                            event_envelope.timestamp,
+                            *current_version,
+                            now,
+                        ) =>
+                    {
+                        continue
+                    }
+                    _ => {}
+                }
+                event_envelopes.push(event_envelope);
+                if event_envelopes.len() == limit {
+                    break 'buckets;
+                }
+            }
+            if exhausted {
+                bucket += 1;
+            }
+        }
+        Ok(EventBatch {
+            event_envelopes,
+            next_position,
+        })
+    }
+
+    // Whether an event of the by-position table is delivered, skipped because its write was rejected
+    // or held back because it is still being written.  Events that have not been marked as committed
+    // or rejected are looked up in the events table.
+    async fn resolve_position(
+        &self,
+        row: &Row,
+        reserved_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<Resolution, CassandraEventStoreError> {
+        let committed: Option<bool> = row.get_by_name("committed")?;
+        match committed {
+            Some(true) => return Ok(Resolution::Deliver),
+            Some(false) => return Ok(Resolution::Skip),
+            None => {}
+        }
+        let aggregate_id: String = row.get_r_by_name("aggregate_id")?;
+        let version: i64 = row.get_r_by_name("version")?;
+        let id: Uuid = row.get_r_by_name("id")?;
+        let query = format!(
+            "SELECT id FROM {} WHERE aggregate_id = ? AND version = ?",
+            self.table()
+        );
+        let rows = self
+            .session
+            .query_with_values(query, query_values!(aggregate_id, version))
+            .await?
+            .response_body()?
+            .into_rows()
+            .unwrap_or_default();
+        let stored_id = match rows.first() {
+            Some(row) => Some(row.get_r_by_name("id")?),
+            None => None,
+        };
+        Ok(resolve_uncommitted(
+            id,
+            stored_id,
+            reserved_at,
+            self.gap_timeout,
+            now,
+        ))
+    }
+
+    // Latest reserved global position, or `0` when no position has been reserved yet.
+    async fn current_position(&self) -> Result<i64, CassandraEventStoreError> {
+        let query = format!(
+            "SELECT position FROM {} WHERE id = ?",
+            self.positions_table()
+        );
+        let rows = self
+            .session
+            .query_with_values(query, query_values!(POSITION_ID))
+            .await?
+            .response_body()?
+            .into_rows()
+            .unwrap_or_default();
+        match rows.first() {
+            Some(row) => Ok(row.get_r_by_name("position")?),
+            None => Ok(0),
+        }
+    }
+
+    // Reserve `count` consecutive global positions and return the first one.  The counter is advanced
+    // with a lightweight transaction, which is retried after a jittered exponential backoff while
+    // other writers advance it concurrently.  Fails with `Error::Transient` once `RESERVE_ATTEMPTS`
+    // attempts have lost the race.
+    async fn reserve_positions(&self, count: i64) -> Result<i64, CassandraEventStoreError> {
+        for attempt in 0..RESERVE_ATTEMPTS {
+            if attempt > 0 {
+                tokio::time::sleep(reserve_backoff(attempt)).await;
+            }
+            let position = self.current_position().await?;
+            let (query, values) = if position == 0 {
+                (
+                    format!(
+                        "INSERT INTO {} (id, position) VALUES (?, ?) IF NOT EXISTS",
+                        self.positions_table()
+                    ),
+                    query_values!(POSITION_ID, count),
+                )
+            } else {
+                (
+                    format!(
+                        "UPDATE {} SET position = ? WHERE id = ? IF position = ?",
+                        self.positions_table()
+                    ),
+                    query_values!(position + count, POSITION_ID, position),
+                )
+            };
+            if Self::applied(self.session.query_with_values(query, values).await?)? {
+                return Ok(position + 1);
+            }
+        }
+        Err(Error::transient(format!(
+            "could not reserve global positions after {} attempts",
+            RESERVE_ATTEMPTS
+        ))
+        .into())
+    }
+
+    // Values of an event row, in the order of `EVENT_COLUMNS`.
+    fn event_values<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        event_envelope: &EventEnvelope<Event>,
+    ) -> Result<Vec<Value>, CassandraEventStoreError> {
+        Ok(vec![
+            event_envelope.aggregate_id.clone().into(),
+            event_envelope.version.into(),
+            event_envelope.id.into(),
+            event_envelope.aggregate_type.clone().into(),
+            event_envelope.schema_version.into(),
+            self.codec.content_type().into(),
+            Blob::from(self.codec.encode(&event_envelope.data)?).into(),
+            event_envelope.event_type.clone().into(),
+            event_envelope.timestamp.into(),
+            serde_json::to_string(&event_envelope.metadata)?.into(),
+            event_envelope.position.into(),
+        ])
+    }
+
+    // Insert all event envelopes in a single conditional batch and return whether it was applied.
+    // Conditional batches on a single partition are applied atomically, so either every event is
+    // written or none are.  The envelopes are copied to the by-position table beforehand, and marked
+    // as committed or rejected once the batch has been decided.
+    async fn insert<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        event_envelopes: &[EventEnvelope<Event>],
+    ) -> Result<bool, CassandraEventStoreError> {
+        let query = format!(
+            "INSERT INTO {} (bucket, {}, reserved_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            self.by_position_table(),
+            EVENT_COLUMNS
+        );
+        let reserved_at = Utc::now();
+        let batch = event_envelopes
+            .iter()
+            .try_fold(BatchQueryBuilder::new(), |batch, event_envelope| {
+                let mut values = vec![(event_envelope.position / POSITION_BUCKET_SIZE).into()];
+                values.extend(self.event_values(event_envelope)?);
+                values.push(reserved_at.into());
+                Ok::<_, CassandraEventStoreError>(
+                    batch.add_query(query.clone(), QueryValues::SimpleValues(values)),
+                )
+            })?
+            .build()?;
+        self.session.batch(batch).await?;
+        let query = format!(
+            "INSERT INTO {} ({}) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) IF NOT EXISTS",
+            self.table(),
+            EVENT_COLUMNS
+        );
+        let batch = event_envelopes
+            .iter()
+            .try_fold(BatchQueryBuilder::new(), |batch, event_envelope| {
+                Ok::<_, CassandraEventStoreError>(batch.add_query(
+                    query.clone(),
+                    QueryValues::SimpleValues(self.event_values(event_envelope)?),
+                ))
+            })?
+            .build()?;
+        let applied = Self::applied(self.session.batch(batch).await?)?;
+        let query = format!(
+            "UPDATE {} SET committed = ? WHERE bucket = ? AND position = ?",
+            self.by_position_table()
+        );
+        let batch = event_envelopes
+            .iter()
+            .fold(BatchQueryBuilder::new(), |batch, event_envelope| {
+                batch.add_query(
+                    query.clone(),
+                    query_values!(
+                        applied,
+                        event_envelope.position / POSITION_BUCKET_SIZE,
+                        event_envelope.position
+                    ),
+                )
+            })
+            .build()?;
+        self.session.batch(batch).await?;
+        Ok(applied)
+    }
+
+    // Whether a lightweight transaction was applied.
+    fn applied(envelope: Envelope) -> Result<bool, CassandraEventStoreError> {
+        match envelope
+            .response_body()?
+            .into_rows()
+            .unwrap_or_default()
+            .first()
+        {
+            Some(row) => Ok(row.get_r_by_name("[applied]")?),
+            None => Ok(false),
+        }
+    }
+
+    fn event_envelope<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        row: Row,
+    ) -> Result<Option<EventEnvelope<Event>>, CassandraEventStoreError> {
+        let event_type: String = row.get_r_by_name("event_type")?;
+        // Rows written before the schema_version column existed are at version 1.
+        let schema_version: Option<i64> = row.get_by_name("schema_version")?;
+        let schema_version = schema_version.unwrap_or(1);
+        let content_type: Option<String> = row.get_by_name("content_type")?;
+        let data: Blob = row.get_r_by_name("data")?;
+        let data = data.into_vec();
+        // Data is decoded with the codec of the store when it wrote it, and with the built-in
+        // codec of its content type otherwise.  Rows written before the content_type column existed
+        // hold JSON.
+        let decoded = match content_type {
+            Some(content_type) if content_type == self.codec.content_type() => {
+                self.decode_data(&self.codec, &event_type, schema_version, &data)?
+            }
+            Some(content_type) => self.decode_data(
+                &content_type.parse::<ContentType>()?,
+                &event_type,
+                schema_version,
+                &data,
+            )?,
+            None => self.decode_data(&ContentType::Json, &event_type, schema_version, &data)?,
+        };
+        let Some((schema_version, data)) = decoded else {
+            return Ok(None);
+        };
+        let timestamp: DateTime<Utc> = row.get_r_by_name("timestamp")?;
+        let id: Uuid = row.get_r_by_name("id")?;
+        // Rows written before the metadata column existed have no metadata.
+        let metadata: Option<String> = row.get_by_name("metadata")?;
+        // Rows written before the position column existed have no global position.
+        let position: Option<i64> = row.get_by_name("position")?;
+        Ok(Some(EventEnvelope {
+            id,
+            aggregate_id: row.get_r_by_name("aggregate_id")?,
+            aggregate_type: row.get_r_by_name("aggregate_type")?,
+            schema_version,
+            data,
+            event_type,
+            version: row.get_r_by_name("version")?,
+            timestamp,
+            metadata: match metadata {
+                Some(metadata) => serde_json::from_str(metadata.as_str())?,
+                None => EventMetadata::default(),
+            },
+            position: position.unwrap_or(0),
+        }))
+    }
+
+    // Decode the data of an event with the codec, upcasting it first when it is self-describing,
+    // along with the schema version it has been upcasted to.
+    fn decode_data<Event: EventType + Serialize + DeserializeOwned, D: Codec>(
+        &self,
+        codec: &D,
+        event_type: &str,
+        schema_version: i64,
+        data: &[u8],
+    ) -> Result<Option<(i64, Event)>, CassandraEventStoreError> {
+        if !codec.is_self_describing() {
+            check_codec::<Event, D>(codec, &self.upcasters)?;
+            return Ok(Some((schema_version, codec.decode(data)?)));
+        }
+        let (schema_version, data) =
+            self.upcasters
+                .upcast(event_type, schema_version, codec.decode(data)?)?;
+        Ok(deserialize_data(event_type, data)?.map(|data| (schema_version, data)))
+    }
 }
 
 #[async_trait::async_trait]
-impl EventStore for CassandraEventStore {
-    async fn read<Event: EventType + serde::ser::Serialize + DeserializeOwned>(
+impl<C: Codec + 'static> EventStore for CassandraEventStore<C> {
+    async fn read<Event: EventType + Serialize + DeserializeOwned>(
         &self,
-        _aggregate_id: &String,
+        aggregate_id: &String,
     ) -> Result<Vec<EventEnvelope<Event>>, Error> {
-        todo!()
+        Ok(self.select(aggregate_id, i64::MIN).await?)
     }
 
     async fn read_from<Event: EventType + Serialize + DeserializeOwned>(
         &self,
-        _aggregate_id: &String,
-        _version: i64,
+        aggregate_id: &String,
+        version: i64,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        Ok(self.select(aggregate_id, version).await?)
+    }
+
+    async fn read_range<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        from_version: i64,
+        to_version: i64,
+    ) -> Result<Vec<EventEnvelope<Event>>, Error> {
+        Ok(self
+            .select_range(aggregate_id, from_version, to_version)
+            .await?)
+    }
+
+    async fn read_backward<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        version: i64,
+        limit: usize,
     ) -> Result<Vec<EventEnvelope<Event>>, Error> {
-        todo!()
+        Ok(self.select_backward(aggregate_id, version, limit).await?)
+    }
+
+    fn stream_from<'a, Event: EventType + Serialize + DeserializeOwned>(
+        &'a self,
+        aggregate_id: &'a String,
+        version: i64,
+    ) -> BoxStream<'a, Result<EventEnvelope<Event>, Error>> {
+        paged_stream(version, move |version| async move {
+            Ok(self.select_page(aggregate_id, version).await?)
+        })
+    }
+
+    async fn persist<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        event_envelope: EventEnvelope<Event>,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        let aggregate_id = event_envelope.aggregate_id.as_str();
+        if self.is_deleted(aggregate_id).await? {
+            return Err(Error::StreamDeleted(String::from(aggregate_id)));
+        }
+        // Versions are checked against the current version read beforehand.  Writers racing for
+        // the next version are told apart by the lightweight transaction, so no gap can open up.
+        let current_version = self.current_version(aggregate_id).await?;
+        if (1..=current_version).contains(&event_envelope.version)
+            || !expected_version.is_satisfied_by(current_version)
+        {
+            return Err(VersionConflictError::new(
+                String::from(aggregate_id),
+                expected_version,
+                current_version,
+            )
+            .into());
+        }
+        if event_envelope.version != current_version + 1 {
+            return Err(Error::InvalidArgument(format!(
+                "event envelope at version {} cannot be stored at version {} of aggregate `{}`",
+                event_envelope.version,
+                current_version + 1,
+                aggregate_id
+            )));
+        }
+        let mut event_envelope = event_envelope;
+        event_envelope.position = self.reserve_positions(1).await?;
+        if !self.insert(std::slice::from_ref(&event_envelope)).await? {
+            let current_version = self.current_version(&event_envelope.aggregate_id).await?;
+            return Err(VersionConflictError::new(
+                event_envelope.aggregate_id,
+                expected_version,
+                current_version,
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    async fn append<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        aggregate_id: &String,
+        event_envelopes: Vec<EventEnvelope<Event>>,
+        expected_version: ExpectedVersion,
+    ) -> Result<i64, Error> {
+        if let Some(event_envelope) = event_envelopes
+            .iter()
+            .find(|event_envelope| event_envelope.aggregate_id != *aggregate_id)
+        {
+            return Err(Error::InvalidArgument(format!(
+                "event envelope for aggregate `{}` cannot be appended to aggregate `{}`",
+                event_envelope.aggregate_id, aggregate_id
+            )));
+        }
+        if self.is_deleted(aggregate_id).await? {
+            return Err(Error::StreamDeleted(String::from(aggregate_id)));
+        }
+        let current_version = self.current_version(aggregate_id).await?;
+        if !expected_version.is_satisfied_by(current_version) {
+            return Err(VersionConflictError::new(
+                String::from(aggregate_id),
+                expected_version,
+                current_version,
+            )
+            .into());
+        }
+        // Writers racing for the same versions are told apart by the lightweight transaction.
+        check_versions(aggregate_id, current_version, &event_envelopes)?;
+        if event_envelopes.is_empty() {
+            return Ok(current_version);
+        }
+        let position = self.reserve_positions(event_envelopes.len() as i64).await?;
+        let event_envelopes: Vec<EventEnvelope<Event>> = event_envelopes
+            .into_iter()
+            .zip(current_version + 1..)
+            .zip(position..)
+            .map(|((mut event_envelope, version), position)| {
+                event_envelope.version = version;
+                event_envelope.position = position;
+                event_envelope
+            })
+            .collect();
+        if !self.insert(&event_envelopes).await? {
+            return Err(VersionConflictError::new(
+                String::from(aggregate_id),
+                expected_version,
+                self.current_version(aggregate_id).await?,
+            )
+            .into());
+        }
+        Ok(current_version + event_envelopes.len() as i64)
+    }
+
+    async fn read_version(&self, aggregate_id: &String) -> Result<i64, Error> {
+        if self.is_deleted(aggregate_id).await? {
+            return Ok(0);
+        }
+        Ok(self.current_version(aggregate_id).await?)
+    }
+
+    async fn read_all<Event: EventType + Serialize + DeserializeOwned>(
+        &self,
+        from_position: i64,
+        limit: usize,
+        filter: &EventFilter,
+    ) -> Result<EventBatch<Event>, Error> {
+        Ok(self.select_all(from_position, limit, filter).await?)
+    }
+
+    async fn soft_delete(
+        &self,
+        aggregate_id: &String,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        self.check_writable(aggregate_id, expected_version).await?;
+        let query = format!(
+            "INSERT INTO {} (aggregate_id, hard) VALUES (?, ?) IF NOT EXISTS",
+            self.tombstones_table()
+        );
+        let envelope = self
+            .session
+            .query_with_values(query, query_values!(aggregate_id.as_str(), false))
+            .await
+            .map_err(CassandraEventStoreError::from)?;
+        if !Self::applied(envelope)? {
+            return Err(Error::StreamDeleted(String::from(aggregate_id)));
+        }
+        Ok(self.discard_positions(aggregate_id, i64::MAX).await?)
+    }
+
+    async fn hard_delete(
+        &self,
+        aggregate_id: &String,
+        expected_version: ExpectedVersion,
+    ) -> Result<(), Error> {
+        if !self.is_deleted(aggregate_id).await? {
+            self.check_writable(aggregate_id, expected_version).await?;
+        }
+        let query = format!(
+            "INSERT INTO {} (aggregate_id, hard) VALUES (?, ?)",
+            self.tombstones_table()
+        );
+        self.session
+            .query_with_values(query, query_values!(aggregate_id.as_str(), true))
+            .await
+            .map_err(CassandraEventStoreError::from)?;
+        self.discard_positions(aggregate_id, i64::MAX).await?;
+        for table in [self.table(), self.metadata_table()] {
+            let query = format!("DELETE FROM {} WHERE aggregate_id = ?", table);
+            self.session
+                .query_with_values(query, query_values!(aggregate_id.as_str()))
+                .await
+                .map_err(CassandraEventStoreError::from)?;
+        }
+        Ok(())
+    }
+
+    async fn truncate_before(&self, aggregate_id: &String, version: i64) -> Result<(), Error> {
+        if self.is_deleted(aggregate_id).await? {
+            return Err(Error::StreamDeleted(String::from(aggregate_id)));
+        }
+        let version = version.min(self.current_version(aggregate_id).await?);
+        self.discard_positions(aggregate_id, version).await?;
+        let query = format!(
+            "DELETE FROM {} WHERE aggregate_id = ? AND version < ?",
+            self.table()
+        );
+        self.session
+            .query_with_values(query, query_values!(aggregate_id.as_str(), version))
+            .await
+            .map_err(CassandraEventStoreError::from)?;
+        Ok(())
+    }
+
+    async fn read_stream_metadata(&self, aggregate_id: &String) -> Result<StreamMetadata, Error> {
+        Ok(self
+            .select_metadata(aggregate_id)
+            .await?
+            .unwrap_or_default())
     }
 
-    async fn persist<Event: EventType + serde::ser::Serialize + DeserializeOwned>(
+    async fn write_stream_metadata(
         &self,
-        _event_envelope: EventEnvelope<Event>,
+        aggregate_id: &String,
+        metadata: StreamMetadata,
     ) -> Result<(), Error> {
-        todo!()
+        if self.is_deleted(aggregate_id).await? {
+            return Err(Error::StreamDeleted(String::from(aggregate_id)));
+        }
+        let query = format!(
+            "INSERT INTO {} (aggregate_id, metadata) VALUES (?, ?)",
+            self.metadata_table()
+        );
+        self.session
+            .query_with_values(
+                query,
+                query_values!(aggregate_id.as_str(), serde_json::to_string(&metadata)?),
+            )
+            .await
+            .map_err(CassandraEventStoreError::from)?;
+        Ok(())
+    }
+
+    async fn scavenge(&self) -> Result<usize, Error> {
+        let query = format!(
+            "SELECT aggregate_id, metadata FROM {}",
+            self.metadata_table()
+        );
+        // The metadata of every stream is scanned `page_size` rows at a time.
+        let mut pager = self.session.paged(self.page_size);
+        let mut rows = pager.query(query);
+        let mut scavenged = 0;
+        loop {
+            for row in rows.next().await.map_err(CassandraEventStoreError::from)? {
+                let aggregate_id: String = row
+                    .get_r_by_name("aggregate_id")
+                    .map_err(CassandraEventStoreError::from)?;
+                let metadata: String = row
+                    .get_r_by_name("metadata")
+                    .map_err(CassandraEventStoreError::from)?;
+                let metadata: StreamMetadata = serde_json::from_str(&metadata)?;
+                if !metadata.has_retention() || self.is_deleted(&aggregate_id).await? {
+                    continue;
+                }
+                scavenged += self.scavenge_stream(&aggregate_id, &metadata).await?;
+            }
+            if !rows.has_more() {
+                return Ok(scavenged);
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum CassandraEventStoreError {
+    #[error(transparent)]
+    Driver(Box<cdrs_tokio::Error>),
+    #[error(transparent)]
+    Serialization(#[from] serde_json::Error),
+    #[error(transparent)]
+    EventSourcing(#[from] Error),
+}
+
+impl From<cdrs_tokio::Error> for CassandraEventStoreError {
+    fn from(error: cdrs_tokio::Error) -> Self {
+        CassandraEventStoreError::Driver(Box::new(error))
+    }
+}
+
+impl From<CassandraEventStoreError> for Error {
+    fn from(error: CassandraEventStoreError) -> Self {
+        match error {
+            CassandraEventStoreError::Driver(error) if is_transient(&error) => {
+                Error::transient(error)
+            }
+            CassandraEventStoreError::Driver(error) => Error::backend(error),
+            CassandraEventStoreError::Serialization(error) => error.into(),
+            CassandraEventStoreError::EventSourcing(error) => error,
+        }
+    }
+}
+
+// What `read_all` does with an event of the by-position table.
+#[derive(Debug, PartialEq, Eq)]
+enum Resolution {
+    Deliver,
+    Skip,
+    HoldBack,
+}
+
+// Resolve an event of the by-position table that has not been marked as committed or rejected from
+// the id of the event stored at its version in the events table, if any.
+fn resolve_uncommitted(
+    id: Uuid,
+    stored_id: Option<Uuid>,
+    reserved_at: Option<DateTime<Utc>>,
+    gap_timeout: Duration,
+    now: DateTime<Utc>,
+) -> Resolution {
+    match stored_id {
+        Some(stored_id) if stored_id == id => Resolution::Deliver,
+        Some(_) => Resolution::Skip,
+        None if is_abandoned(reserved_at, gap_timeout, now) => Resolution::Skip,
+        None => Resolution::HoldBack,
+    }
+}
+
+// Whether the writer of a position reserved at the time has given up on it.  Positions copied before
+// the reserved_at column existed have been abandoned.
+fn is_abandoned(
+    reserved_at: Option<DateTime<Utc>>,
+    gap_timeout: Duration,
+    now: DateTime<Utc>,
+) -> bool {
+    match reserved_at {
+        // Positions reserved after now by a writer with a clock ahead have not been abandoned.
+        Some(reserved_at) => (now - reserved_at)
+            .to_std()
+            .is_ok_and(|elapsed| elapsed > gap_timeout),
+        None => true,
+    }
+}
+
+// Random backoff before the attempt to reserve positions, up to `RESERVE_BACKOFF` doubled for every
+// attempt after the second, so that competing writers spread out their retries.
+fn reserve_backoff(attempt: u32) -> Duration {
+    let bound = RESERVE_BACKOFF * 2u32.pow(attempt.saturating_sub(1));
+    bound.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+}
+
+// Whether the driver error is caused by a condition that may clear up when retried.
+fn is_transient(error: &cdrs_tokio::Error) -> bool {
+    match error {
+        cdrs_tokio::Error::Io(_) | cdrs_tokio::Error::Timeout(_) => true,
+        cdrs_tokio::Error::Server { body, .. } => matches!(
+            body.additional_info,
+            AdditionalErrorInfo::Unavailable(_)
+                | AdditionalErrorInfo::Overloaded
+                | AdditionalErrorInfo::IsBootstrapping
+                | AdditionalErrorInfo::Truncate
+                | AdditionalErrorInfo::WriteTimeout(_)
+                | AdditionalErrorInfo::ReadTimeout(_)
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_bounds_reserve_backoff_exponentially() {
+        for _ in 0..100 {
+            assert!(reserve_backoff(1) <= RESERVE_BACKOFF);
+            assert!(reserve_backoff(4) <= RESERVE_BACKOFF * 8);
+        }
+    }
+
+    #[test]
+    fn it_delivers_uncommitted_event_once_stored() {
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        assert_eq!(
+            resolve_uncommitted(id, Some(id), Some(now), Duration::from_secs(10), now),
+            Resolution::Deliver
+        );
+    }
+
+    #[test]
+    fn it_skips_uncommitted_event_when_other_event_was_stored() {
+        let now = Utc::now();
+        assert_eq!(
+            resolve_uncommitted(
+                Uuid::new_v4(),
+                Some(Uuid::new_v4()),
+                Some(now),
+                Duration::from_secs(10),
+                now
+            ),
+            Resolution::Skip
+        );
+    }
+
+    #[test]
+    fn it_holds_back_uncommitted_event_until_gap_timeout() {
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        assert_eq!(
+            resolve_uncommitted(
+                id,
+                None,
+                Some(now - chrono::Duration::seconds(5)),
+                Duration::from_secs(10),
+                now
+            ),
+            Resolution::HoldBack
+        );
+        assert_eq!(
+            resolve_uncommitted(
+                id,
+                None,
+                Some(now - chrono::Duration::seconds(15)),
+                Duration::from_secs(10),
+                now
+            ),
+            Resolution::Skip
+        );
+    }
+
+    #[test]
+    fn it_abandons_positions_after_gap_timeout() {
+        let now = Utc::now();
+        let gap_timeout = Duration::from_secs(10);
+        assert!(!is_abandoned(Some(now), gap_timeout, now));
+        assert!(!is_abandoned(
+            Some(now + chrono::Duration::seconds(1)),
+            gap_timeout,
+            now
+        ));
+        assert!(is_abandoned(
+            Some(now - chrono::Duration::seconds(11)),
+            gap_timeout,
+            now
+        ));
+        assert!(is_abandoned(None, gap_timeout, now));
     }
 }
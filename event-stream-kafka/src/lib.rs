@@ -1,38 +1,160 @@
+use base64::prelude::*;
 use event_sourcing::Error;
 use kafka::client::{FetchOffset, GroupOffsetStorage};
 use kafka::consumer::{Consumer, MessageSets};
+use kafka::error::KafkaCode;
 use retry::delay::Fixed;
 use retry::retry;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use event_sourcing::event::envelope::{deserialize, EventEnvelope};
-use event_sourcing::event::EventType;
+use event_sourcing::codec::{Codec, ContentType, JsonCodec};
+use event_sourcing::event::envelope::{check_codec, decode_value, EventEnvelope};
 use event_sourcing::event::listener::EventListener;
-
-use crate::KafkaEventStreamError::InternalError;
+use event_sourcing::event::metadata::EventMetadata;
+use event_sourcing::event::upcaster::UpcasterRegistry;
+use event_sourcing::event::EventType;
 
 #[derive(Debug, Clone)]
-pub struct KafkaEventStream<Event>
+pub struct KafkaEventStream<Event, C = JsonCodec>
+where
+    Event: EventType + Serialize + DeserializeOwned,
+    C: Codec,
+{
+    // Consumer group whose committed offsets the stream resumes from.
+    group: String,
+    // Topic the change events of the events table are published to.
+    topic: String,
+    brokers: Vec<String>,
+    // Handler every consumed event is passed to.
+    apply: fn(event: EventEnvelope<Event>) -> Result<(), Error>,
+    // Upcasters applied to every consumed event before it is deserialized.
+    upcasters: UpcasterRegistry,
+    // Codec the events were written with.  Events recorded with another content type are decoded
+    // with its built-in codec.
+    codec: C,
+}
+
+impl<Event> KafkaEventStream<Event>
+where
+    Event: EventType + Serialize + DeserializeOwned,
+{
+    /// Create a stream that consumes the topic from the brokers as part of the group and passes
+    /// every event to `apply`, decoding events written as JSON.
+    pub fn new(
+        group: String,
+        topic: String,
+        brokers: Vec<String>,
+        apply: fn(event: EventEnvelope<Event>) -> Result<(), Error>,
+    ) -> Self {
+        Self {
+            group,
+            topic,
+            brokers,
+            apply,
+            upcasters: UpcasterRegistry::default(),
+            codec: JsonCodec,
+        }
+    }
+}
+
+impl<Event, C> KafkaEventStream<Event, C>
 where
     Event: EventType + Serialize + DeserializeOwned,
+    C: Codec,
 {
-    pub group: String,
-    pub topic: String,
-    pub brokers: Vec<String>,
-    pub apply: fn(event: EventEnvelope<Event>) -> Result<(), Error>,
+    /// Upcast events to their current shape before they are deserialized.
+    pub fn with_upcasters(mut self, upcasters: UpcasterRegistry) -> Self {
+        self.upcasters = upcasters;
+        self
+    }
+
+    /// Decode the data of events with the codec they were written with.  Events recorded with
+    /// another content type are decoded with its built-in codec.
+    pub fn with_codec<D: Codec>(self, codec: D) -> KafkaEventStream<Event, D> {
+        KafkaEventStream {
+            group: self.group,
+            topic: self.topic,
+            brokers: self.brokers,
+            apply: self.apply,
+            upcasters: self.upcasters,
+            codec,
+        }
+    }
 }
 
-#[derive(Debug, Clone, thiserror::Error)]
+// Row of the events table as emitted by the connector.  Text columns are emitted as they are, so
+// the metadata holds JSON text, while the data column is a blob that the connector encodes as
+// base64.  The id and timestamp are passed on as emitted.
+#[derive(Debug, Deserialize)]
+struct EventRow {
+    id: Value,
+    aggregate_id: String,
+    aggregate_type: String,
+    // Rows written before the schema_version column existed are at version 1.
+    schema_version: Option<i64>,
+    // Rows copied from tables that held their data as JSON text have no content type.
+    content_type: Option<String>,
+    data: String,
+    event_type: String,
+    version: i64,
+    timestamp: Value,
+    // Rows written before the metadata column existed have no metadata.
+    metadata: Option<String>,
+    // Rows written before the position column existed have no global position.
+    position: Option<i64>,
+}
+
+#[derive(Debug, thiserror::Error)]
 pub(crate) enum KafkaEventStreamError {
-    #[error("Error `{0}`")]
-    InternalError(String),
+    #[error(transparent)]
+    Kafka(#[from] kafka::Error),
+    #[error(transparent)]
+    EventSourcing(#[from] Error),
+}
+
+impl From<KafkaEventStreamError> for Error {
+    fn from(error: KafkaEventStreamError) -> Self {
+        match error {
+            KafkaEventStreamError::Kafka(error) if is_transient(&error) => Error::transient(error),
+            KafkaEventStreamError::Kafka(error) => Error::backend(error),
+            KafkaEventStreamError::EventSourcing(error) => error,
+        }
+    }
+}
+
+// Whether the Kafka error is caused by a condition that may clear up when retried.
+fn is_transient(error: &kafka::Error) -> bool {
+    match error {
+        kafka::Error::Io(_) | kafka::Error::NoHostReachable => true,
+        kafka::Error::Kafka(code)
+        | kafka::Error::TopicPartitionError {
+            error_code: code, ..
+        } => matches!(
+            code,
+            KafkaCode::LeaderNotAvailable
+                | KafkaCode::NotLeaderForPartition
+                | KafkaCode::RequestTimedOut
+                | KafkaCode::BrokerNotAvailable
+                | KafkaCode::ReplicaNotAvailable
+                | KafkaCode::NetworkException
+                | KafkaCode::GroupLoadInProgress
+                | KafkaCode::GroupCoordinatorNotAvailable
+                | KafkaCode::NotCoordinatorForGroup
+                | KafkaCode::NotEnoughReplicas
+                | KafkaCode::NotEnoughReplicasAfterAppend
+                | KafkaCode::RebalanceInProgress
+        ),
+        _ => false,
+    }
 }
 
 #[async_trait::async_trait]
-impl<Event> EventListener for KafkaEventStream<Event>
+impl<Event, C> EventListener for KafkaEventStream<Event, C>
 where
     Event: EventType + Serialize + DeserializeOwned,
+    C: Codec + 'static,
 {
     async fn on_event(&self) -> Result<(), Error> {
         retry(Fixed::from_millis(1000), || {
@@ -43,44 +165,159 @@ where
                 .with_offset_storage(GroupOffsetStorage::Kafka)
                 .create()
             {
-                Ok(consumer) => Self::start_consumer(consumer, self.apply),
-                Err(e) => Err(InternalError(format!("{:?}", e))),
+                Ok(consumer) => self.start_consumer(consumer),
+                Err(e) => Err(KafkaEventStreamError::from(e)),
             }
         })
-        .map_err(|e| e.into())
+        .map_err(|e| e.error.into())
     }
 }
 
-impl<Event> KafkaEventStream<Event>
+impl<Event, C> KafkaEventStream<Event, C>
 where
     Event: EventType + Serialize + DeserializeOwned,
+    C: Codec,
 {
-    fn start_consumer(
-        mut consumer: Consumer,
-        apply: fn(EventEnvelope<Event>) -> Result<(), Error>,
-    ) -> Result<(), KafkaEventStreamError> {
+    fn start_consumer(&self, mut consumer: Consumer) -> Result<(), KafkaEventStreamError> {
         loop {
-            let message_sets: MessageSets = consumer
-                .poll()
-                .map_err(|e| InternalError(format!("{:?}", e)))?;
+            let message_sets: MessageSets = consumer.poll()?;
             for message_set in message_sets.iter() {
                 for message in message_set.messages() {
-                    let serialized_event_envelope = String::from_utf8_lossy(message.value)
-                        .to_string()
-                        .replace("\\\"", "\"")
-                        .replace("\"{", "{")
-                        .replace("}\"", "}");
-                    let event_envelope = deserialize(serialized_event_envelope)
-                        .map_err(|e| InternalError(format!("{:?}", e)))?;
-                    apply(event_envelope).map_err(|e| InternalError(format!("{:?}", e)))?
+                    if let Some(event_envelope) = self.event_envelope(message.value)? {
+                        (self.apply)(event_envelope)?
+                    }
                 }
-                consumer
-                    .consume_messageset(message_set)
-                    .map_err(|e| InternalError(format!("{:?}", e)))?;
+                consumer.consume_messageset(message_set)?;
             }
-            consumer
-                .commit_consumed()
-                .map_err(|e| InternalError(format!("{:?}", e)))?;
+            consumer.commit_consumed()?;
         }
     }
+
+    // Decode the change event emitted for a row of the events table.  The data is decoded with the
+    // codec of the stream when it wrote it, and with the built-in codec of its content type
+    // otherwise.  Rows without a content type hold JSON.
+    fn event_envelope(
+        &self,
+        message: &[u8],
+    ) -> Result<Option<EventEnvelope<Event>>, KafkaEventStreamError> {
+        let event_row: EventRow = serde_json::from_slice(message).map_err(Error::from)?;
+        let data = BASE64_STANDARD
+            .decode(&event_row.data)
+            .map_err(Error::serialization)?;
+        let data = match event_row.content_type {
+            Some(content_type) if content_type == self.codec.content_type() => {
+                self.decode_data(&self.codec, &data)?
+            }
+            Some(content_type) => self.decode_data(&content_type.parse::<ContentType>()?, &data)?,
+            None => self.decode_data(&ContentType::Json, &data)?,
+        };
+        let metadata = match event_row.metadata {
+            Some(metadata) => serde_json::from_str(&metadata).map_err(Error::from)?,
+            None => serde_json::to_value(EventMetadata::default()).map_err(Error::from)?,
+        };
+        let event_envelope = serde_json::json!({
+            "id": event_row.id,
+            "aggregate_id": event_row.aggregate_id,
+            "aggregate_type": event_row.aggregate_type,
+            "schema_version": event_row.schema_version.unwrap_or(1),
+            "data": data,
+            "event_type": event_row.event_type,
+            "version": event_row.version,
+            "timestamp": event_row.timestamp,
+            "metadata": metadata,
+            "position": event_row.position.unwrap_or(0),
+        });
+        Ok(decode_value(event_envelope, &self.upcasters)?)
+    }
+
+    // Decode the data of an event to its JSON data model, going through the event itself when the
+    // codec is not self-describing, which cannot be combined with upcasters or a registry.
+    fn decode_data<D: Codec>(
+        &self,
+        codec: &D,
+        data: &[u8],
+    ) -> Result<Value, KafkaEventStreamError> {
+        if codec.is_self_describing() {
+            return Ok(codec.decode(data)?);
+        }
+        check_codec::<Event, D>(codec, &self.upcasters)?;
+        Ok(serde_json::to_value(codec.decode::<Event>(data)?).map_err(Error::from)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    struct TestEvent {
+        amount: i64,
+        description: String,
+    }
+
+    impl EventType for TestEvent {
+        fn event_type(&self) -> String {
+            String::from("TestEvent")
+        }
+    }
+
+    fn event_stream() -> KafkaEventStream<TestEvent> {
+        KafkaEventStream::new(
+            String::from("group"),
+            String::from("topic"),
+            Vec::new(),
+            |_| Ok(()),
+        )
+    }
+
+    fn event_row(content_type: Value, metadata: Value) -> Vec<u8> {
+        let test_event = TestEvent {
+            amount: 1,
+            description: String::from("A \"quoted\" {description}"),
+        };
+        let data = serde_json::to_vec(&test_event).expect("expected serialized event");
+        serde_json::to_vec(&serde_json::json!({
+            "id": "2e996ba1-03a6-47af-8fd1-2039c6708dd4",
+            "aggregate_id": "aggregate_id",
+            "aggregate_type": "TestAggregate",
+            "schema_version": 1,
+            "content_type": content_type,
+            "data": BASE64_STANDARD.encode(data),
+            "event_type": "TestEvent",
+            "version": 1,
+            "timestamp": "2024-01-01T00:00:00Z",
+            "metadata": metadata,
+            "position": 7,
+        }))
+        .expect("expected serialized row")
+    }
+
+    #[test]
+    fn it_decodes_row_emitted_by_connector() {
+        let metadata = serde_json::to_string(&EventMetadata::default().with_principal("user"))
+            .expect("expected serialized metadata");
+        let event_envelope = event_stream()
+            .event_envelope(&event_row(
+                Value::from("application/json"),
+                Value::from(metadata),
+            ))
+            .expect("expected decoded row")
+            .expect("expected event envelope");
+        assert_eq!(
+            event_envelope.data.description,
+            "A \"quoted\" {description}"
+        );
+        assert_eq!(event_envelope.metadata.principal.as_deref(), Some("user"));
+        assert_eq!(event_envelope.position, 7);
+    }
+
+    #[test]
+    fn it_decodes_row_without_content_type_or_metadata_as_json() {
+        let event_envelope = event_stream()
+            .event_envelope(&event_row(Value::Null, Value::Null))
+            .expect("expected decoded row")
+            .expect("expected event envelope");
+        assert_eq!(event_envelope.data.amount, 1);
+        assert_eq!(event_envelope.metadata, EventMetadata::default());
+    }
 }